@@ -0,0 +1,166 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use bevy_math::Vec3;
+
+use crate::camera::CameraConfig;
+
+/// Camera placement at a frame, linearly interpolated in between.
+#[derive(Clone, Copy)]
+pub struct CameraKeyframe {
+    pub frame: f32,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vfov: f32,
+    pub focus_dist: f32,
+}
+
+impl CameraKeyframe {
+    /// Keyframe holding the placement `config` already has.
+    pub fn from_config(frame: f32, config: &CameraConfig) -> Self {
+        Self {
+            frame,
+            lookfrom: config.lookfrom,
+            lookat: config.lookat,
+            vfov: config.vfov,
+            focus_dist: config.focus_dist,
+        }
+    }
+
+    fn interpolate(&self, next: &Self, frame: f32) -> Self {
+        let t = ((frame - self.frame) / (next.frame - self.frame)).clamp(0., 1.);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+        Self {
+            frame,
+            lookfrom: self.lookfrom.lerp(next.lookfrom, t),
+            lookat: self.lookat.lerp(next.lookat, t),
+            vfov: lerp(self.vfov, next.vfov),
+            focus_dist: lerp(self.focus_dist, next.focus_dist),
+        }
+    }
+}
+
+/// A sequence of frames, each rendered with the camera moved along its
+/// keyframes.
+///
+/// Time is measured in frames: frame `n` exposes from `n + shutter_open` to
+/// `n + shutter_close`, so object keyframes placed at time `n` are reached on
+/// frame `n`. Moving spheres only move during frame 0.
+pub struct Animation {
+    pub frames: Range<u32>,
+    camera: Vec<CameraKeyframe>,
+}
+
+impl Animation {
+    pub fn new(frames: Range<u32>) -> Self {
+        Self {
+            frames,
+            camera: Vec::new(),
+        }
+    }
+
+    pub fn with_camera(mut self, mut keyframes: Vec<CameraKeyframe>) -> Self {
+        keyframes.sort_by(|a, b| a.frame.total_cmp(&b.frame));
+        self.camera = keyframes;
+        self
+    }
+
+    /// Replaces the field of view of every camera keyframe.
+    pub fn with_vfov(mut self, vfov: f32) -> Self {
+        for keyframe in &mut self.camera {
+            keyframe.vfov = vfov;
+        }
+        self
+    }
+
+    /// Configuration rendering `frame` of an animation shot with `config`.
+    pub fn frame_config(&self, config: &CameraConfig, frame: u32) -> CameraConfig {
+        let time = frame as f32;
+        let placement = self
+            .camera_keyframe(time)
+            .unwrap_or_else(|| CameraKeyframe::from_config(time, config));
+
+        CameraConfig {
+            lookfrom: placement.lookfrom,
+            lookat: placement.lookat,
+            vfov: placement.vfov,
+            focus_dist: placement.focus_dist,
+            shutter_open: time + config.shutter_open,
+            shutter_close: time + config.shutter_close,
+            ..config.clone()
+        }
+    }
+
+    fn camera_keyframe(&self, frame: f32) -> Option<CameraKeyframe> {
+        let next = self
+            .camera
+            .partition_point(|keyframe| keyframe.frame <= frame);
+        match next {
+            _ if self.camera.is_empty() => None,
+            0 => Some(self.camera[0]),
+            next if next == self.camera.len() => Some(self.camera[next - 1]),
+            next => Some(self.camera[next - 1].interpolate(&self.camera[next], frame)),
+        }
+    }
+}
+
+/// Path of the image for `frame`. A run of `#` in the file name is replaced
+/// by the zero-padded frame number, which is otherwise appended to the stem.
+pub fn frame_path(path: &Path, frame: u32) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let name = match name.find('#') {
+        Some(start) => {
+            let width = name[start..].chars().take_while(|&c| c == '#').count();
+            format!(
+                "{}{frame:0width$}{}",
+                &name[..start],
+                &name[start + width..]
+            )
+        }
+        None => {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            match path.extension() {
+                Some(extension) => {
+                    format!("{stem}_{frame:04}.{}", extension.to_string_lossy())
+                }
+                None => format!("{stem}_{frame:04}"),
+            }
+        }
+    };
+
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vfov_overrides_every_keyframe() {
+        let config = CameraConfig::default();
+        let keyframe = |frame: f32, x: f32, vfov: f32| CameraKeyframe {
+            lookfrom: Vec3::new(x, 0., 10.),
+            vfov,
+            ..CameraKeyframe::from_config(frame, &config)
+        };
+        let animation = Animation::new(0..10)
+            .with_camera(vec![keyframe(0., 0., 30.), keyframe(8., 4., 60.)])
+            .with_vfov(20.);
+
+        for (frame, x) in [(0, 0.), (4, 2.), (9, 4.)] {
+            let config = animation.frame_config(&config, frame);
+            assert_eq!(config.vfov, 20.);
+            assert_eq!(config.lookfrom, Vec3::new(x, 0., 10.));
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use std::{collections::HashMap, path::Path};
+
+use bevy_color::{ColorToComponents as _, LinearRgba};
+use bevy_math::{Vec2, Vec3};
+use clap::ValueEnum;
+use exr::prelude::*;
+use image::{Rgb, RgbImage};
+use serde::Deserialize;
+
+use crate::{
+    framebuffer::{ExrPrecision, Framebuffer},
+    sampler::mix,
+};
+
+/// Auxiliary pass describing what camera rays hit first, for compositing
+/// and denoising.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Aov {
+    /// Distance from the camera along the ray, infinite where nothing was hit.
+    Depth,
+    /// World space normal facing the camera.
+    Normal,
+    /// Base color of the material.
+    Albedo,
+    /// World space position.
+    Position,
+    /// Texture coordinates.
+    Uv,
+    /// Number of the object hit, 0 for the background.
+    ObjectId,
+    /// Number of the material hit, 0 for the background.
+    MaterialId,
+}
+
+impl Aov {
+    /// Name of the pass, used for its file name and its OpenEXR layer.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Depth => "depth",
+            Self::Normal => "normal",
+            Self::Albedo => "albedo",
+            Self::Position => "position",
+            Self::Uv => "uv",
+            Self::ObjectId => "object_id",
+            Self::MaterialId => "material_id",
+        }
+    }
+
+    /// Channels of the pass in OpenEXR files, stored in the red, green and
+    /// blue channels of its framebuffer in this order.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Self::Depth => &["Z"],
+            Self::Normal | Self::Position => &["X", "Y", "Z"],
+            Self::Albedo => &["R", "G", "B"],
+            Self::Uv => &["U", "V"],
+            Self::ObjectId | Self::MaterialId => &["id"],
+        }
+    }
+}
+
+/// What the camera rays of one pixel hit first. Normals, positions and
+/// texture coordinates average the rays that hit something, with normals
+/// renormalized, and are zero where none did. Albedo averages every ray, with
+/// rays that hit nothing counting as black, so that it lines up with the
+/// antialiased image. Depth and ids come from the nearest hit.
+#[derive(Clone, Copy)]
+pub struct AovPixel {
+    pub depth: f32,
+    pub normal: Vec3,
+    pub albedo: LinearRgba,
+    pub position: Vec3,
+    pub uv: Vec2,
+    pub object_id: u32,
+    pub material_id: u32,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        Self {
+            depth: f32::INFINITY,
+            normal: Vec3::ZERO,
+            albedo: LinearRgba::BLACK,
+            position: Vec3::ZERO,
+            uv: Vec2::ZERO,
+            object_id: 0,
+            material_id: 0,
+        }
+    }
+}
+
+/// Every auxiliary pass of a render, stored row by row from the top.
+pub struct AovBuffers {
+    width: u32,
+    height: u32,
+    pixels: Vec<AovPixel>,
+}
+
+impl AovBuffers {
+    /// Numbers the objects and materials in `ids`, the addresses behind
+    /// [`crate::mesh::Hit::object_id`] and [`crate::material::Material::id`]
+    /// of the nearest hit in every pixel, from 1 in the order they first
+    /// appear, which keeps them the same from run to run.
+    pub(crate) fn new(
+        width: u32,
+        height: u32,
+        mut pixels: Vec<AovPixel>,
+        ids: &[Option<(usize, usize)>],
+    ) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize,
+            "pixel count must match the buffer size"
+        );
+
+        let mut objects = HashMap::new();
+        let mut materials = HashMap::new();
+        for (pixel, ids) in pixels.iter_mut().zip(ids) {
+            if let Some((object, material)) = *ids {
+                let next = objects.len() as u32 + 1;
+                pixel.object_id = *objects.entry(object).or_insert(next);
+                let next = materials.len() as u32 + 1;
+                pixel.material_id = *materials.entry(material).or_insert(next);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[AovPixel] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> &AovPixel {
+        &self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    /// Raw values of `aov`, in the channels listed by [`Aov::channels`],
+    /// with depth repeated in all three.
+    pub fn framebuffer(&self, aov: Aov) -> Framebuffer {
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|pixel| match aov {
+                Aov::Depth => LinearRgba::rgb(pixel.depth, pixel.depth, pixel.depth),
+                Aov::Normal => LinearRgba::rgb(pixel.normal.x, pixel.normal.y, pixel.normal.z),
+                Aov::Albedo => pixel.albedo,
+                Aov::Position => {
+                    LinearRgba::rgb(pixel.position.x, pixel.position.y, pixel.position.z)
+                }
+                Aov::Uv => LinearRgba::rgb(pixel.uv.x, pixel.uv.y, 0.),
+                Aov::ObjectId => LinearRgba::rgb(pixel.object_id as f32, 0., 0.),
+                Aov::MaterialId => LinearRgba::rgb(pixel.material_id as f32, 0., 0.),
+            })
+            .collect();
+        Framebuffer::new(self.width, self.height, pixels)
+    }
+
+    /// Maps `aov` to 8 bits for viewing: near surfaces bright in depth,
+    /// normals from `[-1, 1]`, positions spread over the bounds of the hits
+    /// and a distinct color for every id.
+    pub fn to_image(&self, aov: Aov) -> RgbImage {
+        if aov == Aov::Albedo {
+            return self.framebuffer(aov).to_srgb_image();
+        }
+
+        let hits = || self.pixels.iter().filter(|pixel| pixel.depth.is_finite());
+        let (near, far) = hits().fold((f32::INFINITY, 0f32), |(near, far), pixel| {
+            (near.min(pixel.depth), far.max(pixel.depth))
+        });
+        let (min, max) = hits().fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), pixel| {
+            (min.min(pixel.position), max.max(pixel.position))
+        });
+
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let pixel = self.get(x, y);
+            let color = match aov {
+                Aov::Depth if pixel.depth.is_finite() => {
+                    Vec3::splat(1. - (pixel.depth - near) / (far - near).max(f32::EPSILON))
+                }
+                Aov::Depth => Vec3::ZERO,
+                Aov::Normal => pixel.normal * 0.5 + 0.5,
+                Aov::Position => {
+                    (pixel.position - min) / (max - min).max(Vec3::splat(f32::EPSILON))
+                }
+                Aov::Uv => pixel.uv.extend(0.),
+                Aov::ObjectId => id_color(pixel.object_id),
+                Aov::MaterialId => id_color(pixel.material_id),
+                Aov::Albedo => unreachable!("albedo is converted to sRGB above"),
+            };
+            Rgb(color
+                .clamp(Vec3::ZERO, Vec3::ONE)
+                .to_array()
+                .map(|c| (c * 255.).round() as u8))
+        })
+    }
+
+    /// Writes `beauty` as the RGB channels of an OpenEXR file, along with
+    /// `aovs` as layers named after them. Ids are always stored as 32-bit
+    /// floats, which hold them exactly.
+    pub fn write_exr(
+        &self,
+        path: impl AsRef<Path>,
+        beauty: &Framebuffer,
+        aovs: &[Aov],
+        precision: ExrPrecision,
+    ) -> exr::error::Result<()> {
+        assert_eq!(
+            (beauty.width(), beauty.height()),
+            (self.width, self.height),
+            "beauty must match the size of the passes"
+        );
+
+        let samples = |values: Vec<f32>, exact: bool| match precision {
+            ExrPrecision::Half if !exact => {
+                FlatSamples::F16(values.into_iter().map(f16::from_f32).collect())
+            }
+            _ => FlatSamples::F32(values),
+        };
+        let channel = |framebuffer: &Framebuffer, index: usize| -> Vec<f32> {
+            framebuffer
+                .pixels()
+                .iter()
+                .map(|color| color.to_f32_array()[index])
+                .collect()
+        };
+
+        let mut channels = SmallVec::<[AnyChannel<FlatSamples>; 4]>::new();
+        for (index, name) in ["R", "G", "B"].into_iter().enumerate() {
+            channels.push(AnyChannel::new(
+                name,
+                samples(channel(beauty, index), false),
+            ));
+        }
+        for &aov in aovs {
+            let framebuffer = self.framebuffer(aov);
+            let exact = matches!(aov, Aov::ObjectId | Aov::MaterialId);
+            for (index, name) in aov.channels().iter().enumerate() {
+                let name = format!("{}.{name}", aov.name());
+                channels.push(AnyChannel::new(
+                    name.as_str(),
+                    samples(channel(&framebuffer, index), exact),
+                ));
+            }
+        }
+
+        let size = (self.width as usize, self.height as usize);
+        let layer = Layer::new(
+            size,
+            LayerAttributes::default(),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(channels),
+        );
+        Image::from_layer(layer).write().to_file(path)
+    }
+}
+
+/// Bright color picked by hashing `id`, black for the background.
+fn id_color(id: u32) -> Vec3 {
+    if id == 0 {
+        return Vec3::ZERO;
+    }
+
+    let hash = mix(0, id as u64);
+    let channel = |shift: u32| 0.25 + 0.75 * ((hash >> shift) & 0xff) as f32 / 255.;
+    Vec3::new(channel(0), channel(8), channel(16))
+}
@@ -1,20 +1,161 @@
-use bevy_color::{Color, ColorToComponents as _, ColorToPacked, LinearRgba, Srgba};
-use bevy_math::Vec3;
-use image::{Rgb, RgbImage};
-use indicatif::ProgressBar;
-use rand::Rng;
+use std::{
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use bevy_color::{Color, ColorToComponents as _, LinearRgba};
+use bevy_math::{Vec2, Vec3};
+use clap::ValueEnum;
+use rand::{Rng, RngCore};
+use rayon::prelude::*;
+use serde::Deserialize;
 
 use crate::{
-    mesh::Mesh,
-    utils::{degrees_to_radians, random_vec_in_unit_disk},
+    aov::{AovBuffers, AovPixel},
+    checkpoint::Checkpoint,
+    framebuffer::{Framebuffer, PixelStats},
+    mesh::{Hit, Mesh, World},
+    progress::{ProgressReporter, RenderHandle},
+    sampler::{Sampler, SamplerKind, SamplerRng},
+    tile::{tiles, RenderedTile, TileOrder},
+    tonemap::{white_balance, ToneMapping, NEUTRAL_TEMPERATURE},
+    utils::{concentric_disk, degrees_to_radians},
     Ray,
 };
 
+/// Samples every pixel takes before adaptive sampling first estimates its
+/// noise, and at most per round afterwards.
+const ADAPTIVE_BATCH: usize = 16;
+/// Most samples adaptive sampling spends on a pixel, as a multiple of
+/// `samples_per_pixel`.
+const ADAPTIVE_MAX_FACTOR: usize = 16;
+/// Most samples per pixel in a pass of progressive rendering, which bounds
+/// the work lost when a render is interrupted between checkpoints.
+const MAX_PASS_SAMPLES: usize = 64;
+/// Most camera rays per pixel traced for the auxiliary passes.
+const AOV_SAMPLES: usize = 16;
+
+/// Times within which scenes open and close the shutter, over which
+/// [`Sphere::moving`](crate::mesh::Sphere::moving) spheres move. Animations
+/// expose frame `n` within this interval offset by `n`.
+pub const SHUTTER_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+
+/// Strategy used to find light arriving at each path vertex.
+#[derive(Clone, Copy, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Integrator {
+    /// Relies on scattered rays randomly hitting emitters.
+    Naive,
+    /// Samples the lights directly at every non-specular hit.
+    LightSampling,
+    /// Combines light and BSDF samples using the power heuristic.
+    #[default]
+    Mis,
+}
+
+/// How much light the shutter lets through over the time it is open.
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Shutter {
+    /// Fully open for the whole interval.
+    #[default]
+    Box,
+    /// Opens linearly until the middle of the interval, then closes.
+    Triangle,
+    /// Openness at evenly spaced points from opening to closing, linearly
+    /// interpolated in between.
+    Custom(Vec<f32>),
+}
+
+impl FromStr for Shutter {
+    type Err = String;
+
+    /// Parses `box`, `triangle` or a comma-separated custom curve.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Self::Box),
+            "triangle" => Ok(Self::Triangle),
+            curve => curve
+                .split(',')
+                .map(|value| value.trim().parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map(Self::Custom)
+                .map_err(|_| {
+                    format!("expected `box`, `triangle` or comma-separated numbers, got `{s}`")
+                }),
+        }
+    }
+}
+
+impl Shutter {
+    /// Maps `u` uniform in `[0, 1)` to a fraction of the shutter interval
+    /// distributed in proportion to the openness.
+    pub fn sample(&self, u: f32) -> f32 {
+        match self {
+            Self::Box => u,
+            Self::Triangle if u < 0.5 => (u / 2.).sqrt(),
+            Self::Triangle => 1. - ((1. - u) / 2.).sqrt(),
+            Self::Custom(curve) => Self::sample_curve(curve, u),
+        }
+    }
+
+    fn sample_curve(curve: &[f32], u: f32) -> f32 {
+        let segments = curve.len().saturating_sub(1);
+        let width = 1. / segments as f32;
+        let area = |a: f32, b: f32| (a.max(0.) + b.max(0.)) / 2. * width;
+
+        let total: f32 = curve.windows(2).map(|pair| area(pair[0], pair[1])).sum();
+        if total <= 0. {
+            return u;
+        }
+
+        let mut remaining = u * total;
+        for (index, pair) in curve.windows(2).enumerate() {
+            let (from, to) = (pair[0].max(0.), pair[1].max(0.));
+            let segment = area(from, to);
+            if remaining > segment && index + 1 < segments {
+                remaining -= segment;
+                continue;
+            }
+
+            // Invert the area under the linear ramp from `from` to `to`.
+            let remaining = remaining.min(segment) / width;
+            let slope = to - from;
+            let t = if slope.abs() < 1e-6 {
+                remaining / from.max(f32::MIN_POSITIVE)
+            } else {
+                ((from * from + 2. * slope * remaining).max(0.).sqrt() - from) / slope
+            };
+            return (index as f32 + t.clamp(0., 1.)) * width;
+        }
+
+        u
+    }
+}
+
+#[derive(Clone)]
 pub struct CameraConfig {
     pub width: u32,
     pub height: u32,
+    /// Samples per pixel, or their average when adaptive sampling is enabled.
     pub samples_per_pixel: usize,
+    /// Relative standard error below which adaptive sampling stops sampling
+    /// a pixel, spending the samples it saves on noisier ones.
+    pub noise_threshold: Option<f32>,
+    /// Wall-clock time after which no more passes are started. Until then
+    /// passes are added regardless of `samples_per_pixel`.
+    pub time_budget: Option<Duration>,
+    /// Safety limit on bounces for paths Russian roulette keeps alive, such
+    /// as light trapped between mirrors. It sits far beyond where roulette
+    /// ends paths, so it does not shape the image the way a depth cutoff would.
     pub max_depth: usize,
+    /// Bounces after which paths are terminated by Russian roulette.
+    pub roulette_depth: usize,
     pub vfov: f32,
     pub lookfrom: Vec3,
     pub lookat: Vec3,
@@ -22,6 +163,25 @@ pub struct CameraConfig {
     pub defocus_angle: f32,
     pub focus_dist: f32,
     pub background: Color,
+    pub integrator: Integrator,
+    pub sampler: SamplerKind,
+    /// Seeds the sampler, making renders reproducible.
+    pub seed: u64,
+    /// Time at which the shutter opens, as seen by [`Ray::time`], within
+    /// [`SHUTTER_RANGE`] except for animation frames.
+    pub shutter_open: f32,
+    /// Time at which the shutter closes, as seen by [`Ray::time`], within
+    /// [`SHUTTER_RANGE`] except for animation frames.
+    pub shutter_close: f32,
+    pub shutter: Shutter,
+    /// Exposure compensation in stops applied before tone mapping.
+    pub exposure: f32,
+    /// Temperature, in kelvin, of the light that should appear white.
+    pub white_balance: f32,
+    pub tone_mapping: ToneMapping,
+    /// Side of the square tiles the image is rendered in, in pixels.
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
 }
 
 impl Default for CameraConfig {
@@ -30,7 +190,10 @@ impl Default for CameraConfig {
             width: 100,
             height: 100,
             samples_per_pixel: 10,
-            max_depth: 10,
+            noise_threshold: None,
+            time_budget: None,
+            max_depth: 1024,
+            roulette_depth: 3,
             vfov: 90.,
             lookfrom: Vec3::ZERO,
             lookat: Vec3::new(0., 0., -1.),
@@ -38,19 +201,34 @@ impl Default for CameraConfig {
             defocus_angle: 0.,
             focus_dist: 10.,
             background: Color::linear_rgb(0.7, 0.8, 1.),
+            integrator: Integrator::default(),
+            sampler: SamplerKind::default(),
+            seed: 0,
+            shutter_open: 0.,
+            shutter_close: 1.,
+            shutter: Shutter::default(),
+            exposure: 0.,
+            white_balance: NEUTRAL_TEMPERATURE,
+            tone_mapping: ToneMapping::default(),
+            tile_size: 32,
+            tile_order: TileOrder::default(),
         }
     }
 }
 
+type TileCallback = Box<dyn Fn(&RenderedTile) + Send + Sync>;
+
 pub struct Camera {
     config: CameraConfig,
     center: Vec3,
     pixel00_loc: Vec3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
-    pixel_samples_scale: f32,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    on_tile: Option<TileCallback>,
+    handle: RenderHandle,
+    progress: Option<Box<dyn ProgressReporter>>,
 }
 
 impl Camera {
@@ -88,82 +266,704 @@ impl Camera {
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            pixel_samples_scale: 1. / config.samples_per_pixel as f32,
             config,
             defocus_disk_u,
             defocus_disk_v,
+            on_tile: None,
+            handle: RenderHandle::new(),
+            progress: None,
         }
     }
 
-    pub fn render(&self, mesh: &(impl Mesh + Sync)) -> RgbImage {
-        let bar = ProgressBar::new(self.config.width as u64 * self.config.height as u64);
-        let image = RgbImage::from_par_fn(self.config.width, self.config.height, |x, y| {
-            let rgb = Rgb(Srgba::from(self.render_pixel(mesh, x, y)).to_u8_array_no_alpha());
-            bar.inc(1);
-            rgb
+    /// Handle through which other threads can follow and cancel the renders
+    /// of this camera.
+    pub fn handle(&self) -> RenderHandle {
+        self.handle.clone()
+    }
+
+    /// Reports the progress of every render to `progress`, such as an
+    /// [`indicatif::ProgressBar`].
+    pub fn with_progress(mut self, progress: impl ProgressReporter + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Calls `on_tile` from the rendering threads with every tile as soon
+    /// as it has taken more samples, for example to show live progress.
+    /// A channel sender can forward the tiles to another thread.
+    pub fn with_tile_callback(
+        mut self,
+        on_tile: impl Fn(&RenderedTile) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_tile = Some(Box::new(on_tile));
+        self
+    }
+
+    /// Renders `mesh`, sampling the meshes in `lights` directly at every
+    /// diffuse hit. An empty `lights` world falls back to pure path tracing.
+    ///
+    /// A render cancelled through [`Camera::handle`] returns the image
+    /// rendered so far.
+    pub fn render(&self, mesh: &(impl Mesh + Sync), lights: &World) -> Framebuffer {
+        let mut checkpoint = Checkpoint::new(&self.config);
+        self.render_passes(&mut checkpoint, mesh, lights, false, &mut |_| {});
+        checkpoint.framebuffer()
+    }
+
+    /// Renders like [`Camera::render`], continuing from `checkpoint`, in
+    /// passes that each double the samples taken so far, up to
+    /// `MAX_PASS_SAMPLES` per pixel. The samples taken after every pass are
+    /// handed to `on_pass`. The result is the same as that of a single pass.
+    /// A cancelled render leaves some pixels with more samples than others,
+    /// which continuing from the returned checkpoint evens out.
+    pub fn render_progressive(
+        &self,
+        mesh: &(impl Mesh + Sync),
+        lights: &World,
+        mut checkpoint: Checkpoint,
+        mut on_pass: impl FnMut(&Checkpoint),
+    ) -> Checkpoint {
+        assert!(
+            checkpoint.mismatch(&self.config).is_none(),
+            "checkpoint must come from a render with the same settings"
+        );
+        self.render_passes(&mut checkpoint, mesh, lights, true, &mut on_pass);
+        checkpoint
+    }
+
+    fn render_passes(
+        &self,
+        checkpoint: &mut Checkpoint,
+        mesh: &(impl Mesh + Sync),
+        lights: &World,
+        progressive: bool,
+        on_pass: &mut dyn FnMut(&Checkpoint),
+    ) {
+        let samples_per_pixel = self.config.samples_per_pixel;
+        let pixel_count = checkpoint.pixels().len();
+        let mut deadline = self.config.time_budget.map(Deadline::new);
+
+        let total = match deadline {
+            Some(_) => None,
+            None => Some(samples_per_pixel as u64 * pixel_count as u64),
+        };
+        self.handle
+            .start(total, checkpoint.framebuffer(), checkpoint.samples());
+        if let Some(progress) = &self.progress {
+            progress.start(total, checkpoint.samples());
+        }
+
+        match self.config.noise_threshold {
+            Some(threshold) => {
+                self.render_adaptive(checkpoint, mesh, lights, threshold, &mut deadline, on_pass)
+            }
+            None if progressive || deadline.is_some() => {
+                let target = match deadline {
+                    Some(_) => usize::MAX,
+                    None => samples_per_pixel,
+                };
+                while !self.handle.is_cancelled() {
+                    let samples = checkpoint.pixels().iter().map(PixelStats::samples);
+                    let taken = samples.min().unwrap_or(target);
+                    // Checkpoints of time-budgeted renders may hold more.
+                    if taken >= target {
+                        break;
+                    }
+                    let mut count = taken
+                        .clamp(1, MAX_PASS_SAMPLES)
+                        .min(target.saturating_sub(taken));
+                    if let Some(deadline) = &deadline {
+                        // A single sample per pixel measures the pace first.
+                        let fitting = deadline
+                            .samples_left()
+                            .map_or(1, |left| left / pixel_count.max(1) as u64);
+                        count = count.min(fitting as usize);
+                    }
+                    if count == 0 {
+                        break;
+                    }
+
+                    // Pixels a cancelled pass reached already have some of the samples.
+                    let pass_end = taken + count;
+                    let taken =
+                        self.sample_pixels(checkpoint.pixels_mut(), mesh, lights, |stats| {
+                            pass_end.saturating_sub(stats.samples())
+                        });
+                    if let Some(deadline) = &mut deadline {
+                        deadline.record(taken);
+                    }
+                    on_pass(checkpoint);
+                }
+            }
+            None => {
+                self.sample_pixels(checkpoint.pixels_mut(), mesh, lights, |stats| {
+                    samples_per_pixel.saturating_sub(stats.samples())
+                });
+            }
+        }
+
+        self.handle.finish();
+        if let Some(progress) = &self.progress {
+            progress.finish();
+        }
+    }
+
+    /// Samples pixels in rounds, leaving out the ones whose noise is below
+    /// `threshold` until the budget of `samples_per_pixel` per pixel on
+    /// average, or the time budget, runs out. Every round is a pass handed
+    /// to `on_pass`.
+    fn render_adaptive(
+        &self,
+        checkpoint: &mut Checkpoint,
+        mesh: &(impl Mesh + Sync),
+        lights: &World,
+        threshold: f32,
+        deadline: &mut Option<Deadline>,
+        on_pass: &mut dyn FnMut(&Checkpoint),
+    ) {
+        let samples_per_pixel = self.config.samples_per_pixel;
+        // A time budget replaces the sample budget.
+        let (first_round, max_samples) = match deadline {
+            Some(_) => (ADAPTIVE_BATCH, usize::MAX),
+            None => (
+                ADAPTIVE_BATCH.min(samples_per_pixel),
+                samples_per_pixel * ADAPTIVE_MAX_FACTOR,
+            ),
+        };
+        let converged = |stats: &PixelStats| {
+            stats.samples() >= max_samples || stats.relative_error() <= threshold
+        };
+
+        // With a time budget, a single sample per pixel measures the pace
+        // before the first round commits to more.
+        if let Some(deadline) = deadline {
+            let taken = self.sample_pixels(checkpoint.pixels_mut(), mesh, lights, |stats| {
+                (stats.samples() < first_round) as usize
+            });
+            deadline.record(taken);
+            if taken > 0 {
+                on_pass(checkpoint);
+            }
+        }
+
+        let pixel_count = checkpoint.pixels().len().max(1) as u64;
+        let fitting = deadline
+            .as_ref()
+            .and_then(Deadline::samples_left)
+            .map_or(usize::MAX, |left| (left / pixel_count) as usize);
+        let taken = self.sample_pixels(checkpoint.pixels_mut(), mesh, lights, |stats| {
+            first_round.saturating_sub(stats.samples()).min(fitting)
         });
-        bar.finish();
-        image
+        if let Some(deadline) = deadline {
+            deadline.record(taken);
+        }
+        if taken > 0 {
+            on_pass(checkpoint);
+        }
+
+        let mut remaining = match deadline {
+            Some(_) => u64::MAX,
+            None => {
+                let budget = samples_per_pixel as u64 * checkpoint.pixels().len() as u64;
+                budget.saturating_sub(checkpoint.samples())
+            }
+        };
+        while !self.handle.is_cancelled() {
+            let pixels = checkpoint.pixels();
+            let active = pixels.iter().filter(|stats| !converged(stats)).count() as u64;
+            // Until the pace is known, active pixels take a single sample.
+            let fitting = deadline.as_ref().map_or(u64::MAX, |deadline| {
+                deadline.samples_left().unwrap_or(active)
+            });
+            let batch = match remaining.min(fitting).checked_div(active) {
+                Some(batch) if batch > 0 => batch.min(ADAPTIVE_BATCH as u64) as usize,
+                _ => break,
+            };
+
+            let taken = self.sample_pixels(checkpoint.pixels_mut(), mesh, lights, |stats| {
+                if converged(stats) {
+                    0
+                } else {
+                    batch.min(max_samples - stats.samples())
+                }
+            });
+            remaining = remaining.saturating_sub(taken);
+            if let Some(deadline) = deadline {
+                deadline.record(taken);
+            }
+            on_pass(checkpoint);
+        }
     }
 
-    fn render_pixel(&self, world: &impl Mesh, x: u32, y: u32) -> LinearRgba {
-        let mut rng = rand::thread_rng();
+    /// Adds as many samples to every pixel as `count` asks for given its
+    /// statistics so far, returning how many were taken in total. Once the
+    /// render is cancelled, the pixels still waiting are left as they are.
+    fn sample_pixels(
+        &self,
+        pixels: &mut [PixelStats],
+        mesh: &(impl Mesh + Sync),
+        lights: &World,
+        count: impl Fn(&PixelStats) -> usize + Sync,
+    ) -> u64 {
+        let width = self.config.width as usize;
+        let tiles = tiles(
+            self.config.width,
+            self.config.height,
+            self.config.tile_size,
+            self.config.tile_order,
+        );
 
-        let color: Vec3 = (0..self.config.samples_per_pixel)
-            .map(|_| {
-                let ray = self.get_ray(&mut rng, x, y);
-                self.ray_color(&ray, world, self.config.max_depth).to_vec3()
+        // Workers take the tiles in order from a shared counter, so that they
+        // finish roughly in order too. Each copies the statistics of its tile
+        // out and back, leaving the lock free while it renders.
+        let next_tile = AtomicUsize::new(0);
+        let taken = AtomicU64::new(0);
+        let pixels = Mutex::new(pixels);
+        rayon::broadcast(|_| {
+            while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                if self.handle.is_cancelled() {
+                    break;
+                }
+
+                let indices = || tile.pixels().map(|(x, y)| y as usize * width + x as usize);
+                let mut stats: Vec<PixelStats> = {
+                    let pixels = pixels.lock().unwrap();
+                    indices().map(|index| pixels[index]).collect()
+                };
+
+                let mut tile_taken = 0;
+                for ((x, y), stats) in tile.pixels().zip(&mut stats) {
+                    if self.handle.is_cancelled() {
+                        break;
+                    }
+                    let count = count(stats);
+                    if count > 0 {
+                        self.render_pixel(mesh, lights, x, y, stats, count);
+                        tile_taken += count as u64;
+                    }
+                }
+                if tile_taken == 0 {
+                    continue;
+                }
+
+                {
+                    let mut pixels = pixels.lock().unwrap();
+                    for (index, stats) in indices().zip(&stats) {
+                        pixels[index] = *stats;
+                    }
+                }
+                taken.fetch_add(tile_taken, Ordering::Relaxed);
+
+                let rendered = RenderedTile {
+                    tile: *tile,
+                    pixels: stats.iter().map(PixelStats::mean).collect(),
+                };
+                self.handle.update(&rendered, tile_taken);
+                if let Some(progress) = &self.progress {
+                    progress.advance(tile_taken);
+                }
+                if let Some(on_tile) = &self.on_tile {
+                    on_tile(&rendered);
+                }
+            }
+        });
+
+        taken.into_inner()
+    }
+
+    /// Traces camera rays into `mesh` without scattering them, recording
+    /// what they hit first for compositing. Pixels take the first of the
+    /// samples the render takes, up to `AOV_SAMPLES`, so that the passes
+    /// line up with the antialiased image.
+    pub fn render_aovs(&self, mesh: &(impl Mesh + Sync)) -> AovBuffers {
+        let (width, height) = (self.config.width, self.config.height);
+        let samples = self.config.samples_per_pixel.clamp(1, AOV_SAMPLES);
+
+        let (pixels, ids) = (0..width as usize * height as usize)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (index as u32 % width, index as u32 / width);
+                self.aov_pixel(mesh, x, y, samples)
             })
-            .sum();
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+
+        AovBuffers::new(width, height, pixels, &ids)
+    }
+
+    /// Auxiliary passes of the pixel at (`x`, `y`), along with the addresses
+    /// of the object and material of its nearest hit.
+    fn aov_pixel(
+        &self,
+        mesh: &impl Mesh,
+        x: u32,
+        y: u32,
+        samples: usize,
+    ) -> (AovPixel, Option<(usize, usize)>) {
+        let mut sampler = self
+            .config
+            .sampler
+            .build(self.config.samples_per_pixel, self.config.seed);
+        let mut pixel = AovPixel::default();
+        let mut albedo = Vec3::ZERO;
+        let mut hits = 0;
+        let mut ids = None;
+
+        for sample in 0..samples {
+            sampler.start_pixel_sample(x, y, sample);
+            let ray = self.get_ray(sampler.as_mut(), x, y);
+            let mut rng = SamplerRng(sampler.as_mut());
+            let Some(hit) = mesh.hit(&ray, &(0.001..f32::INFINITY).into(), &mut rng) else {
+                continue;
+            };
 
-        let color = self.pixel_samples_scale * color;
+            hits += 1;
+            pixel.normal += hit.normal;
+            pixel.position += hit.point;
+            pixel.uv += hit.uv;
+            albedo += hit.material.albedo(hit.uv, hit.point).to_vec3();
+
+            // Ray directions are not normalized, so `distance` is not one.
+            let depth = hit.distance * ray.direction.length();
+            if depth < pixel.depth {
+                pixel.depth = depth;
+                ids = Some((hit.object_id, hit.material.id()));
+            }
+        }
+
+        // Geometry is averaged over the rays that hit something, which leaves
+        // it zero only where nothing was hit.
+        let hits = hits.max(1) as f32;
+        pixel.normal = (pixel.normal / hits).normalize_or_zero();
+        pixel.position /= hits;
+        pixel.uv /= hits;
+        albedo /= samples as f32;
+        pixel.albedo = LinearRgba::rgb(albedo.x, albedo.y, albedo.z);
+        (pixel, ids)
+    }
+
+    /// Applies exposure, white balance and tone mapping to a rendered
+    /// framebuffer, leaving every channel within `[0, 1]`.
+    pub fn tone_map(&self, framebuffer: &Framebuffer) -> Framebuffer {
+        let gain = 2f32.powf(self.config.exposure) * white_balance(self.config.white_balance);
+
+        framebuffer.map(|color| {
+            let color = self.config.tone_mapping.apply(gain * color.to_vec3());
+            LinearRgba::rgb(color.x, color.y, color.z)
+        })
+    }
+
+    /// Adds the next `count` samples of the pixel at (`x`, `y`) to `stats`.
+    fn render_pixel(
+        &self,
+        world: &impl Mesh,
+        lights: &World,
+        x: u32,
+        y: u32,
+        stats: &mut PixelStats,
+        count: usize,
+    ) {
+        // Every sample only depends on its index, whichever thread renders it.
+        let mut sampler = self
+            .config
+            .sampler
+            .build(self.config.samples_per_pixel, self.config.seed);
+
+        for sample in stats.samples()..stats.samples() + count {
+            sampler.start_pixel_sample(x, y, sample);
+            let ray = self.get_ray(sampler.as_mut(), x, y);
+            let mut rng = SamplerRng(sampler.as_mut());
+            stats.add(self.ray_color(&mut rng, ray, world, lights).to_vec3());
+        }
+    }
+
+    fn ray_color(
+        &self,
+        rng: &mut dyn RngCore,
+        mut ray: Ray,
+        world: &impl Mesh,
+        lights: &World,
+    ) -> LinearRgba {
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        // Density the current ray was sampled with, if its origin also sampled the lights.
+        let mut scatter_pdf: Option<f32> = None;
+
+        // Paths end by Russian roulette; `max_depth` is only a safety net.
+        for depth in 0..self.config.max_depth {
+            let Some(hit) = world.hit(&ray, &(0.001..f32::INFINITY).into(), rng) else {
+                color += throughput * self.config.background.to_linear().to_vec3();
+                break;
+            };
+
+            let mut color_from_emission = hit.material.emitted(hit.uv, hit.point).to_vec3();
+            if let Some(scatter_pdf) = scatter_pdf {
+                if color_from_emission != Vec3::ZERO {
+                    color_from_emission *= self.emission_weight(&ray, lights, scatter_pdf);
+                }
+            }
+            color += throughput * color_from_emission;
+
+            let Some(scatter) = hit.material.scatter(&ray, &hit, rng) else {
+                break;
+            };
+
+            let sample_lights = !lights.is_empty()
+                && !matches!(self.config.integrator, Integrator::Naive)
+                && scatter.pdf.is_some();
+            if sample_lights {
+                color += throughput * self.direct_light(&ray, &hit, world, lights, rng);
+            }
+
+            throughput *= scatter.attenuation.to_vec3();
+            scatter_pdf = scatter.pdf.filter(|_| sample_lights);
+
+            if depth + 1 >= self.config.roulette_depth {
+                let survival = throughput.max_element().min(1.);
+                if rng.gen::<f32>() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+
+            ray = scatter.scattered;
+        }
 
         LinearRgba::rgb(color.x, color.y, color.z)
     }
 
-    fn ray_color(&self, ray: &Ray, world: &impl Mesh, depth: usize) -> LinearRgba {
-        let color_vec = if depth <= 0 {
-            Vec3::ZERO
-        } else if let Some(hit) = world.hit(ray, &(0.001..f32::INFINITY).into()) {
-            let color_from_emission = hit.material.emitted(hit.uv, hit.point).to_vec3();
+    /// Weight of emission found by a scattered ray whose origin also sampled the lights.
+    fn emission_weight(&self, ray: &Ray, lights: &World, scatter_pdf: f32) -> f32 {
+        let light_pdf = lights.pdf_value(ray);
+
+        match self.config.integrator {
+            Integrator::Naive => 1.,
+            Integrator::LightSampling if light_pdf > 0. => 0.,
+            Integrator::LightSampling => 1.,
+            Integrator::Mis => power_heuristic(scatter_pdf, light_pdf),
+        }
+    }
+
+    /// Radiance scattered at `hit` from a shadow ray towards a random light,
+    /// divided by the light density.
+    fn direct_light(
+        &self,
+        ray: &Ray,
+        hit: &Hit,
+        world: &impl Mesh,
+        lights: &World,
+        rng: &mut dyn RngCore,
+    ) -> Vec3 {
+        let direction = lights.random(hit.point, ray.time, rng);
+        let shadow_ray = Ray::new(hit.point, direction, ray.time);
 
-            if let Some(scatter) = hit.material.scatter(ray, &hit) {
-                let color_from_scatter = scatter.attenuation.to_vec3()
-                    * self
-                        .ray_color(&scatter.scattered, world, depth - 1)
-                        .to_vec3();
+        let light_pdf = lights.pdf_value(&shadow_ray);
+        let bsdf = hit.material.eval(ray, hit, shadow_ray.direction).to_vec3();
+        if light_pdf <= 0. || bsdf == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
 
-                color_from_scatter + color_from_emission
-            } else {
-                color_from_emission
+        let Some(light_hit) = world.hit(&shadow_ray, &(0.001..f32::INFINITY).into(), rng) else {
+            return Vec3::ZERO;
+        };
+        let emitted = light_hit
+            .material
+            .emitted(light_hit.uv, light_hit.point)
+            .to_vec3();
+
+        let weight = match self.config.integrator {
+            Integrator::Mis => {
+                let scatter_pdf = hit.material.pdf(ray, hit, shadow_ray.direction);
+                power_heuristic(light_pdf, scatter_pdf)
             }
-        } else {
-            self.config.background.to_linear().to_vec3()
+            _ => 1.,
         };
 
-        LinearRgba::rgb(color_vec.x, color_vec.y, color_vec.z)
+        weight * bsdf * emitted / light_pdf
     }
 
-    fn get_ray(&self, mut rng: impl Rng, x: u32, y: u32) -> Ray {
-        let offset = Self::sample_square(&mut rng);
+    fn get_ray(&self, sampler: &mut dyn Sampler, x: u32, y: u32) -> Ray {
+        let offset = Self::sample_square(sampler.get_2d());
         let pixel_sample = self.pixel00_loc
             + ((x as f32 + offset.x) * self.pixel_delta_u)
             + ((y as f32 + offset.y) * self.pixel_delta_v);
+        // Drawn even without defocus blur, keeping later dimensions in place.
+        let lens = sampler.get_2d();
         let ray_origin = if self.config.defocus_angle <= 0. {
             self.center
         } else {
-            self.defocus_disk_sample(&mut rng)
+            self.defocus_disk_sample(lens)
         };
 
-        Ray::new(ray_origin, pixel_sample - ray_origin, rng.gen())
+        Ray::new(
+            ray_origin,
+            pixel_sample - ray_origin,
+            self.shutter_time(sampler.get_1d()),
+        )
+    }
+
+    fn shutter_time(&self, u: f32) -> f32 {
+        let fraction = self.config.shutter.sample(u);
+        self.config.shutter_open + (self.config.shutter_close - self.config.shutter_open) * fraction
     }
 
-    fn sample_square(mut rng: impl Rng) -> Vec3 {
-        Vec3::new(rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5, 0.)
+    fn sample_square(u: Vec2) -> Vec3 {
+        Vec3::new(u.x - 0.5, u.y - 0.5, 0.)
     }
 
-    fn defocus_disk_sample(&self, rng: impl Rng) -> Vec3 {
-        let point = random_vec_in_unit_disk(rng);
+    fn defocus_disk_sample(&self, u: Vec2) -> Vec3 {
+        let point = concentric_disk(u);
         self.center + (point.x * self.defocus_disk_u) + (point.y * self.defocus_disk_v)
     }
 }
+
+/// Wall-clock limit on a render, predicting how many more samples fit in it
+/// from the pace of the samples taken so far.
+struct Deadline {
+    start: Instant,
+    end: Instant,
+    samples: u64,
+}
+
+impl Deadline {
+    fn new(budget: Duration) -> Self {
+        let start = Instant::now();
+        Self {
+            start,
+            end: start + budget,
+            samples: 0,
+        }
+    }
+
+    fn record(&mut self, samples: u64) {
+        self.samples += samples;
+    }
+
+    /// Samples that can still be taken before the deadline, or `None` until
+    /// some were taken to tell the pace.
+    fn samples_left(&self) -> Option<u64> {
+        if self.samples == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        let pace = (now - self.start).as_secs_f64() / self.samples as f64;
+        Some((self.end.saturating_duration_since(now).as_secs_f64() / pace) as u64)
+    }
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let pdf2 = pdf * pdf;
+    pdf2 / (pdf2 + other_pdf * other_pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resuming_past_the_target_takes_no_samples() {
+        let config = CameraConfig {
+            width: 4,
+            height: 3,
+            samples_per_pixel: 2,
+            ..CameraConfig::default()
+        };
+        let mut checkpoint = Checkpoint::new(&config);
+        for stats in checkpoint.pixels_mut() {
+            for _ in 0..100 {
+                stats.add(Vec3::ONE);
+            }
+        }
+
+        let camera = Camera::new(config);
+        let checkpoint =
+            camera.render_progressive(&World::new(), &World::new(), checkpoint, |_| {
+                panic!("no pass should run")
+            });
+        assert_eq!(checkpoint.samples(), 100 * 12);
+    }
+
+    #[test]
+    fn cancelling_before_the_render_starts_stops_it() {
+        let camera = Camera::new(CameraConfig {
+            width: 4,
+            height: 3,
+            samples_per_pixel: 2,
+            ..CameraConfig::default()
+        });
+        let handle = camera.handle();
+
+        handle.cancel();
+        camera.render(&World::new(), &World::new());
+        assert_eq!(handle.progress().samples_done, 0);
+        assert!(!handle.is_cancelled());
+
+        // Only the render the cancel arrived before stops.
+        camera.render(&World::new(), &World::new());
+        assert_eq!(handle.progress().samples_done, 2 * 12);
+    }
+
+    /// Fraction of the light let through by time `t` of the shutter interval
+    /// with `curve` as openness.
+    fn curve_cdf(curve: &[f32], t: f32) -> f32 {
+        let width = 1. / (curve.len() - 1) as f32;
+        let area = |from: f32, to: f32, s: f32| width * (from * s + (to - from) * s * s / 2.);
+
+        let (mut before, mut total) = (0., 0.);
+        for (index, pair) in curve.windows(2).enumerate() {
+            let (from, to) = (pair[0].max(0.), pair[1].max(0.));
+            let s = (t / width - index as f32).clamp(0., 1.);
+            before += area(from, to, s);
+            total += area(from, to, 1.);
+        }
+        before / total
+    }
+
+    #[test]
+    fn shutter_times_follow_the_openness() {
+        let shutters = [
+            (Shutter::Box, vec![1., 1.]),
+            (Shutter::Triangle, vec![0., 1., 0.]),
+            (Shutter::Custom(vec![0., 1., 0.5]), vec![0., 1., 0.5]),
+            (
+                Shutter::Custom(vec![2., 0., 0., 1., 3.]),
+                vec![2., 0., 0., 1., 3.],
+            ),
+            (Shutter::Custom(vec![-1., 1., 1.]), vec![-1., 1., 1.]),
+        ];
+
+        for (shutter, curve) in shutters {
+            let mut previous = 0.;
+            for step in 0..1000 {
+                let u = (step as f32 + 0.5) / 1000.;
+                let t = shutter.sample(u);
+                assert!((0. ..=1.).contains(&t) && t >= previous);
+                assert!(
+                    (curve_cdf(&curve, t) - u).abs() < 1e-3,
+                    "{curve:?} samples {t} for {u}"
+                );
+                previous = t;
+            }
+        }
+    }
+
+    #[test]
+    fn rays_are_timed_while_the_shutter_is_open() {
+        let camera = Camera::new(CameraConfig {
+            shutter_open: 0.25,
+            shutter_close: 0.75,
+            shutter: Shutter::Custom(vec![0., 3., 1., 0.]),
+            ..CameraConfig::default()
+        });
+        let mut sampler = SamplerKind::Random.build(16, 0);
+
+        for index in 0..1000 {
+            sampler.start_pixel_sample(3, 4, index);
+            let time = camera.get_ray(sampler.as_mut(), 3, 4).time;
+            assert!((0.25..=0.75).contains(&time), "{time}");
+        }
+        for u in [0., 1. - f32::EPSILON] {
+            assert!((0.25..=0.75).contains(&camera.shutter_time(u)));
+        }
+    }
+}
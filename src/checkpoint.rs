@@ -0,0 +1,491 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read},
+    path::{Path, PathBuf},
+};
+
+use bevy_color::{ColorToComponents as _, LinearRgba};
+use clap::ValueEnum;
+
+use crate::{
+    camera::{CameraConfig, Shutter},
+    framebuffer::{Framebuffer, PixelStats},
+    sampler::{mix, SamplerKind},
+};
+
+const MAGIC: &[u8; 8] = b"RTCKPT03";
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(PathBuf, io::Error),
+    /// The file is not a checkpoint, or was cut short.
+    Format(PathBuf),
+    /// The checkpoint was made with settings that draw different samples.
+    Mismatch(PathBuf, &'static str),
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "{}: {err}", path.display()),
+            Self::Format(path) => write!(f, "{}: not a render checkpoint", path.display()),
+            Self::Mismatch(path, setting) => write!(
+                f,
+                "{}: checkpoint was made with a different {setting}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(_, err) => Some(err),
+            Self::Format(_) | Self::Mismatch(..) => None,
+        }
+    }
+}
+
+/// Samples accumulated in every pixel of a render so far, along with the
+/// settings they were drawn with.
+///
+/// Every sample only depends on the seed, the pixel and its index, so a
+/// render continued from a checkpoint gives the same image as one that was
+/// never interrupted.
+pub struct Checkpoint {
+    width: u32,
+    height: u32,
+    samples_per_pixel: usize,
+    noise_threshold: Option<f32>,
+    /// Whether a time budget rather than the sample count decides when the
+    /// render ends, which leaves pixels with any number of samples.
+    time_budget: bool,
+    sampler: SamplerKind,
+    seed: u64,
+    /// Hash of the other settings that change what the samples see.
+    settings: u64,
+    /// Hash of the description of the scene rendered.
+    scene: u64,
+    pixels: Vec<PixelStats>,
+}
+
+impl Checkpoint {
+    /// Checkpoint of a render with `config` that has not taken any samples.
+    pub fn new(config: &CameraConfig) -> Self {
+        Self {
+            width: config.width,
+            height: config.height,
+            samples_per_pixel: config.samples_per_pixel,
+            noise_threshold: config.noise_threshold,
+            time_budget: config.time_budget.is_some(),
+            sampler: config.sampler,
+            seed: config.seed,
+            settings: settings_hash(config),
+            scene: 0,
+            pixels: vec![PixelStats::default(); config.width as usize * config.height as usize],
+        }
+    }
+
+    /// Ties the checkpoint to the scene described by `description`, such as
+    /// the contents of a scene file, so that it is not resumed with another.
+    pub fn with_scene(mut self, description: &[u8]) -> Self {
+        self.scene = hash_bytes(description);
+        self
+    }
+
+    pub fn pixels(&self) -> &[PixelStats] {
+        &self.pixels
+    }
+
+    pub(crate) fn pixels_mut(&mut self) -> &mut [PixelStats] {
+        &mut self.pixels
+    }
+
+    /// Samples taken over all pixels.
+    pub fn samples(&self) -> u64 {
+        self.pixels.iter().map(|stats| stats.samples() as u64).sum()
+    }
+
+    /// Samples taken per pixel on average.
+    pub fn mean_samples(&self) -> f64 {
+        self.samples() as f64 / self.pixels.len().max(1) as f64
+    }
+
+    /// Mean of the samples taken in every pixel.
+    pub fn framebuffer(&self) -> Framebuffer {
+        let pixels = self.pixels.iter().map(PixelStats::mean).collect();
+        Framebuffer::new(self.width, self.height, pixels)
+    }
+
+    /// Name of the first setting that differs from `config` among those
+    /// deciding which samples are taken, and how many.
+    pub fn mismatch(&self, config: &CameraConfig) -> Option<&'static str> {
+        if (self.width, self.height) != (config.width, config.height) {
+            Some("image size")
+        } else if self.samples_per_pixel != config.samples_per_pixel {
+            Some("sample count")
+        } else if self.noise_threshold != config.noise_threshold {
+            Some("noise threshold")
+        } else if self.time_budget != config.time_budget.is_some() {
+            Some("time budget")
+        } else if self.sampler != config.sampler {
+            Some("sampler")
+        } else if self.seed != config.seed {
+            Some("seed")
+        } else if self.settings != settings_hash(config) {
+            Some("integrator, path depth, camera placement, shutter or background")
+        } else {
+            None
+        }
+    }
+
+    /// Reads a checkpoint made with `config` of the scene described by
+    /// `scene`, as passed to [`Checkpoint::with_scene`].
+    pub fn read(
+        path: impl AsRef<Path>,
+        config: &CameraConfig,
+        scene: &[u8],
+    ) -> Result<Self, CheckpointError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|err| CheckpointError::Io(path.into(), err))?;
+
+        let checkpoint =
+            Self::decode(&mut BufReader::new(file)).map_err(|err| match err.kind() {
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                    CheckpointError::Format(path.into())
+                }
+                _ => CheckpointError::Io(path.into(), err),
+            })?;
+
+        if checkpoint.scene != hash_bytes(scene) {
+            return Err(CheckpointError::Mismatch(path.into(), "scene"));
+        }
+        match checkpoint.mismatch(config) {
+            Some(setting) => Err(CheckpointError::Mismatch(path.into(), setting)),
+            None => Ok(checkpoint),
+        }
+    }
+
+    /// Writes the checkpoint next to `path` before moving it in place, so that
+    /// an interrupted write leaves the previous checkpoint intact.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+
+        let write = || {
+            let mut writer = BufWriter::new(File::create(&partial)?);
+            self.encode(&mut writer)?;
+            writer.into_inner()?.sync_all()?;
+            fs::rename(&partial, path)
+        };
+        write().map_err(|err| CheckpointError::Io(path.into(), err))
+    }
+
+    fn encode(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let sampler = self
+            .sampler
+            .to_possible_value()
+            .expect("samplers are never skipped");
+        let sampler = sampler.get_name().as_bytes();
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&self.width.to_le_bytes())?;
+        writer.write_all(&self.height.to_le_bytes())?;
+        writer.write_all(&(self.samples_per_pixel as u64).to_le_bytes())?;
+        // NaN stands for no threshold.
+        writer.write_all(&self.noise_threshold.unwrap_or(f32::NAN).to_le_bytes())?;
+        writer.write_all(&[self.time_budget as u8])?;
+        writer.write_all(&[sampler.len() as u8])?;
+        writer.write_all(sampler)?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        writer.write_all(&self.settings.to_le_bytes())?;
+        writer.write_all(&self.scene.to_le_bytes())?;
+
+        for stats in &self.pixels {
+            writer.write_all(&stats.to_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+
+        if read_array::<8>(reader)? != *MAGIC {
+            return Err(invalid());
+        }
+
+        let width = u32::from_le_bytes(read_array(reader)?);
+        let height = u32::from_le_bytes(read_array(reader)?);
+        let samples_per_pixel = u64::from_le_bytes(read_array(reader)?) as usize;
+        let noise_threshold = Some(f32::from_le_bytes(read_array(reader)?)).filter(|t| !t.is_nan());
+        let time_budget = match read_array(reader)? {
+            [0] => false,
+            [1] => true,
+            _ => return Err(invalid()),
+        };
+
+        let [length] = read_array(reader)?;
+        let mut sampler = vec![0; length as usize];
+        reader.read_exact(&mut sampler)?;
+        let sampler = std::str::from_utf8(&sampler)
+            .ok()
+            .and_then(|name| SamplerKind::from_str(name, false).ok())
+            .ok_or_else(invalid)?;
+
+        let seed = u64::from_le_bytes(read_array(reader)?);
+        let settings = u64::from_le_bytes(read_array(reader)?);
+        let scene = u64::from_le_bytes(read_array(reader)?);
+
+        let pixels = (0..width as usize * height as usize)
+            .map(|_| read_array(reader).map(|bytes| PixelStats::from_bytes(&bytes)))
+            .collect::<io::Result<_>>()?;
+
+        // Anything after the last pixel means the file is not what it seems.
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            width,
+            height,
+            samples_per_pixel,
+            noise_threshold,
+            time_budget,
+            sampler,
+            seed,
+            settings,
+            scene,
+            pixels,
+        })
+    }
+}
+
+/// Hash of the settings of `config` that change what the samples see, other
+/// than those checkpoints store on their own.
+fn settings_hash(config: &CameraConfig) -> u64 {
+    let (shutter, curve) = match &config.shutter {
+        Shutter::Box => (0, &[][..]),
+        Shutter::Triangle => (1, &[][..]),
+        Shutter::Custom(curve) => (2, &curve[..]),
+    };
+    let integers = [
+        config.integrator as u64,
+        config.max_depth as u64,
+        config.roulette_depth as u64,
+        shutter,
+    ];
+    let floats = [
+        config.vfov,
+        config.defocus_angle,
+        config.focus_dist,
+        config.shutter_open,
+        config.shutter_close,
+    ]
+    .into_iter()
+    .chain(
+        [config.lookfrom, config.lookat, config.vup]
+            .into_iter()
+            .flat_map(|v| v.to_array()),
+    )
+    .chain(LinearRgba::from(config.background).to_f32_array())
+    .chain(curve.iter().copied());
+
+    integers
+        .into_iter()
+        .chain(floats.map(|value| value.to_bits() as u64))
+        .fold(0, mix)
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes
+        .chunks(8)
+        .fold(mix(0, bytes.len() as u64), |hash, chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            mix(hash, u64::from_le_bytes(word))
+        })
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy_color::Color;
+    use bevy_math::Vec3;
+
+    use super::*;
+    use crate::camera::Integrator;
+
+    fn config() -> CameraConfig {
+        CameraConfig {
+            width: 3,
+            height: 2,
+            noise_threshold: Some(0.05),
+            seed: 42,
+            shutter: Shutter::Custom(vec![0., 1., 0.5]),
+            ..CameraConfig::default()
+        }
+    }
+
+    fn checkpoint() -> Checkpoint {
+        let mut checkpoint = Checkpoint::new(&config()).with_scene(b"cornell box");
+        for (index, stats) in checkpoint.pixels_mut().iter_mut().enumerate() {
+            for sample in 0..=index {
+                stats.add(Vec3::new(index as f32, sample as f32, 0.5));
+            }
+        }
+        checkpoint
+    }
+
+    fn encoded(checkpoint: &Checkpoint) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        checkpoint.encode(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let checkpoint = checkpoint();
+        let decoded = Checkpoint::decode(&mut &encoded(&checkpoint)[..]).unwrap();
+
+        assert_eq!((decoded.width, decoded.height), (3, 2));
+        assert_eq!(decoded.samples_per_pixel, checkpoint.samples_per_pixel);
+        assert_eq!(decoded.noise_threshold, Some(0.05));
+        assert!(!decoded.time_budget);
+        assert!(decoded.sampler == checkpoint.sampler);
+        assert_eq!(decoded.seed, 42);
+        assert_eq!(decoded.settings, checkpoint.settings);
+        assert_eq!(decoded.scene, checkpoint.scene);
+        assert_eq!(decoded.samples(), checkpoint.samples());
+        assert_eq!(
+            decoded.framebuffer().pixels(),
+            checkpoint.framebuffer().pixels()
+        );
+        assert_eq!(encoded(&decoded), encoded(&checkpoint));
+        assert_eq!(decoded.mismatch(&config()), None);
+    }
+
+    #[test]
+    fn missing_threshold_round_trips() {
+        let config = CameraConfig {
+            noise_threshold: None,
+            ..config()
+        };
+        let checkpoint = Checkpoint::new(&config);
+        let decoded = Checkpoint::decode(&mut &encoded(&checkpoint)[..]).unwrap();
+        assert_eq!(decoded.noise_threshold, None);
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let bytes = encoded(&checkpoint());
+        let kind = |bytes: &[u8]| Checkpoint::decode(&mut &bytes[..]).err().map(|e| e.kind());
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert_eq!(kind(&wrong_magic), Some(io::ErrorKind::InvalidData));
+        assert_eq!(
+            kind(&bytes[..bytes.len() - 1]),
+            Some(io::ErrorKind::UnexpectedEof)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(kind(&trailing), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn different_settings_are_rejected() {
+        let checkpoint = checkpoint();
+        let changes: [(_, fn(&mut CameraConfig)); 14] = [
+            ("image size", |c| c.width = 4),
+            ("sample count", |c| c.samples_per_pixel += 1),
+            ("noise threshold", |c| c.noise_threshold = None),
+            ("sampler", |c| c.sampler = SamplerKind::Halton),
+            ("seed", |c| c.seed = 7),
+            ("integrator", |c| c.integrator = Integrator::Naive),
+            ("max depth", |c| c.max_depth = 8),
+            ("roulette depth", |c| c.roulette_depth = 5),
+            ("camera position", |c| c.lookfrom = Vec3::ONE),
+            ("field of view", |c| c.vfov = 40.),
+            ("defocus", |c| c.defocus_angle = 1.),
+            ("shutter", |c| c.shutter_close = 0.5),
+            ("shutter curve", |c| c.shutter = Shutter::Triangle),
+            ("background", |c| c.background = Color::BLACK),
+        ];
+
+        for (name, change) in changes {
+            let mut config = config();
+            change(&mut config);
+            assert!(
+                checkpoint.mismatch(&config).is_some(),
+                "{name} was accepted"
+            );
+        }
+
+        // Settings that only change how samples are shown are fine.
+        let config = CameraConfig {
+            exposure: 2.,
+            tile_size: 8,
+            ..config()
+        };
+        assert_eq!(checkpoint.mismatch(&config), None);
+    }
+
+    #[test]
+    fn time_budget_checkpoints_only_resume_with_a_budget() {
+        let budgeted = CameraConfig {
+            time_budget: Some(Duration::from_millis(500)),
+            ..config()
+        };
+        let mut checkpoint = Checkpoint::new(&budgeted);
+        for stats in checkpoint.pixels_mut() {
+            for _ in 0..1000 {
+                stats.add(Vec3::ONE);
+            }
+        }
+        let decoded = Checkpoint::decode(&mut &encoded(&checkpoint)[..]).unwrap();
+        assert!(decoded.time_budget);
+
+        // Far past `samples_per_pixel`, which only a budget can explain.
+        assert_eq!(decoded.mismatch(&config()), Some("time budget"));
+        let longer = CameraConfig {
+            time_budget: Some(Duration::from_secs(10)),
+            ..config()
+        };
+        assert_eq!(decoded.mismatch(&longer), None);
+        assert_eq!(
+            Checkpoint::new(&config()).mismatch(&budgeted),
+            Some("time budget")
+        );
+    }
+
+    #[test]
+    fn reading_rejects_other_scenes() {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.bin", std::process::id()));
+        checkpoint().write(&path).unwrap();
+
+        let read = Checkpoint::read(&path, &config(), b"cornell box");
+        assert_eq!(read.unwrap().samples(), checkpoint().samples());
+        let read = Checkpoint::read(&path, &config(), b"bouncing spheres");
+        assert!(matches!(read, Err(CheckpointError::Mismatch(_, "scene"))));
+        let other = CameraConfig {
+            seed: 1,
+            ..config()
+        };
+        let read = Checkpoint::read(&path, &other, b"cornell box");
+        assert!(matches!(read, Err(CheckpointError::Mismatch(_, "seed"))));
+
+        fs::remove_file(path).unwrap();
+    }
+}
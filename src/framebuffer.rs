@@ -0,0 +1,325 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write as _},
+    path::Path,
+};
+
+use bevy_color::{ColorToPacked, LinearRgba, Srgba};
+use bevy_math::Vec3;
+use clap::ValueEnum;
+use exr::prelude::f16;
+use image::{Rgb, RgbImage};
+
+use crate::tonemap::luminance;
+
+/// Storage type of the channels written to OpenEXR files.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ExrPrecision {
+    /// 16-bit floats, enough for most grading work at half the size.
+    Half,
+    #[default]
+    Float,
+}
+
+/// Running mean and variance of the samples taken in one pixel.
+#[derive(Clone, Copy, Default)]
+pub struct PixelStats {
+    sum: Vec3,
+    samples: usize,
+    /// Mean luminance and sum of squared deviations from it, after Welford.
+    luminance_mean: f64,
+    luminance_m2: f64,
+}
+
+impl PixelStats {
+    /// Size of the little-endian encoding checkpoints store.
+    pub(crate) const ENCODED_SIZE: usize = 36;
+
+    pub fn add(&mut self, color: Vec3) {
+        self.sum += color;
+        self.samples += 1;
+
+        let luminance = luminance(color) as f64;
+        let delta = luminance - self.luminance_mean;
+        self.luminance_mean += delta / self.samples as f64;
+        self.luminance_m2 += delta * (luminance - self.luminance_mean);
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn mean(&self) -> LinearRgba {
+        let mean = self.sum / self.samples.max(1) as f32;
+        LinearRgba::rgb(mean.x, mean.y, mean.z)
+    }
+
+    /// Standard error of the mean luminance relative to the mean itself,
+    /// infinite until there are enough samples to tell.
+    pub fn relative_error(&self) -> f32 {
+        if self.samples < 2 {
+            return f32::INFINITY;
+        }
+
+        let variance = self.luminance_m2 / (self.samples - 1) as f64;
+        let standard_error = (variance / self.samples as f64).sqrt();
+        // Judge nearly black pixels against a floor rather than their tiny mean.
+        (standard_error / self.luminance_mean.max(1e-3)) as f32
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        let fields = [
+            &self.sum.x.to_le_bytes()[..],
+            &self.sum.y.to_le_bytes(),
+            &self.sum.z.to_le_bytes(),
+            &(self.samples as u64).to_le_bytes(),
+            &self.luminance_mean.to_le_bytes(),
+            &self.luminance_m2.to_le_bytes(),
+        ];
+        let mut offset = 0;
+        for field in fields {
+            bytes[offset..offset + field.len()].copy_from_slice(field);
+            offset += field.len();
+        }
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8; Self::ENCODED_SIZE]) -> Self {
+        let f32_at =
+            |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+        Self {
+            sum: Vec3::new(f32_at(0), f32_at(4), f32_at(8)),
+            samples: u64_at(12) as usize,
+            luminance_mean: f64::from_bits(u64_at(20)),
+            luminance_m2: f64::from_bits(u64_at(28)),
+        }
+    }
+}
+
+/// Linear radiance of every pixel, stored row by row from the top.
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<LinearRgba>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32, pixels: Vec<LinearRgba>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize,
+            "pixel count must match the framebuffer size"
+        );
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[LinearRgba] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> LinearRgba {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: LinearRgba) {
+        self.pixels[y as usize * self.width as usize + x as usize] = color;
+    }
+
+    pub fn map(&self, f: impl Fn(LinearRgba) -> LinearRgba) -> Self {
+        Self {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().copied().map(f).collect(),
+        }
+    }
+
+    /// Converts to 8-bit sRGB, clipping everything above 1.
+    pub fn to_srgb_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            Rgb(Srgba::from(self.get(x, y)).to_u8_array_no_alpha())
+        })
+    }
+
+    pub fn write_exr(
+        &self,
+        path: impl AsRef<Path>,
+        precision: ExrPrecision,
+    ) -> exr::error::Result<()> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let pixel = |x: usize, y: usize| self.pixels[y * width + x];
+
+        match precision {
+            ExrPrecision::Half => exr::prelude::write_rgb_file(path, width, height, |x, y| {
+                let color = pixel(x, y);
+                (
+                    f16::from_f32(color.red),
+                    f16::from_f32(color.green),
+                    f16::from_f32(color.blue),
+                )
+            }),
+            ExrPrecision::Float => exr::prelude::write_rgb_file(path, width, height, |x, y| {
+                let color = pixel(x, y);
+                (color.red, color.green, color.blue)
+            }),
+        }
+    }
+
+    /// Writes a little-endian colour Portable Float Map.
+    pub fn write_pfm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        // A negative scale marks the data as little-endian.
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+
+        // PFM stores rows from the bottom up.
+        for row in self.pixels.chunks_exact(self.width as usize).rev() {
+            for color in row {
+                for channel in [color.red, color.green, color.blue] {
+                    writer.write_all(&channel.to_le_bytes())?;
+                }
+            }
+        }
+
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(colors: &[Vec3]) -> PixelStats {
+        let mut stats = PixelStats::default();
+        for &color in colors {
+            stats.add(color);
+        }
+        stats
+    }
+
+    /// Relative standard error computed in two passes over the luminances.
+    fn two_pass_relative_error(colors: &[Vec3]) -> f64 {
+        let luminances: Vec<f64> = colors.iter().map(|&c| luminance(c) as f64).collect();
+        let count = luminances.len() as f64;
+        let mean = luminances.iter().sum::<f64>() / count;
+        let variance = luminances.iter().map(|l| (l - mean).powi(2)).sum::<f64>() / (count - 1.);
+        (variance / count).sqrt() / mean
+    }
+
+    fn colors() -> Vec<Vec3> {
+        (0..100)
+            .map(|i| {
+                let i = i as f32;
+                Vec3::new(
+                    (i * 0.37).sin().abs(),
+                    (i * 0.11) % 1.,
+                    0.5 + (i * 0.73).cos(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mean_averages_every_sample() {
+        let stats = stats(&[Vec3::new(1., 2., 3.), Vec3::new(3., 2., 1.)]);
+        assert_eq!(stats.samples(), 2);
+        assert_eq!(stats.mean(), LinearRgba::rgb(2., 2., 2.));
+        assert_eq!(PixelStats::default().mean(), LinearRgba::BLACK);
+    }
+
+    #[test]
+    fn welford_variance_matches_two_passes() {
+        let colors = colors();
+        let error = stats(&colors).relative_error() as f64;
+        let expected = two_pass_relative_error(&colors);
+        assert!(
+            (error - expected).abs() < 1e-5 * expected,
+            "{error} vs {expected}"
+        );
+
+        // Samples 1 to 5 have a variance of 2.5, hence an error of 0.5 / 3.
+        let stats = stats(&[1., 2., 3., 4., 5.].map(Vec3::splat));
+        assert!((stats.relative_error() - 0.5f32.sqrt() / 3.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn welford_variance_survives_large_offsets() {
+        let colors: Vec<Vec3> = colors().iter().map(|&c| c + 1e4).collect();
+        let error = stats(&colors).relative_error() as f64;
+        let expected = two_pass_relative_error(&colors);
+        assert!(
+            (error - expected).abs() < 1e-3 * expected,
+            "{error} vs {expected}"
+        );
+    }
+
+    #[test]
+    fn relative_error_needs_two_samples() {
+        assert_eq!(PixelStats::default().relative_error(), f32::INFINITY);
+        assert_eq!(stats(&[Vec3::ONE]).relative_error(), f32::INFINITY);
+        assert_eq!(stats(&[Vec3::ONE, Vec3::ONE]).relative_error(), 0.);
+    }
+
+    #[test]
+    fn nearly_black_pixels_are_judged_against_a_floor() {
+        let stats = stats(&[Vec3::ZERO, Vec3::splat(2e-6)]);
+        // Standard error of 1e-6 over the 1e-3 floor rather than the mean.
+        assert!((stats.relative_error() - 1e-3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let stats = stats(&colors());
+        let decoded = PixelStats::from_bytes(&stats.to_bytes());
+        assert_eq!(decoded.sum, stats.sum);
+        assert_eq!(decoded.samples, stats.samples);
+        assert_eq!(decoded.luminance_mean, stats.luminance_mean);
+        assert_eq!(decoded.luminance_m2, stats.luminance_m2);
+    }
+
+    #[test]
+    fn pfm_stores_rows_from_the_bottom_up() {
+        let (width, height) = (3, 2);
+        let pixels = (0..width * height)
+            .map(|i| LinearRgba::rgb(i as f32, i as f32 + 0.25, -(i as f32) - 0.5))
+            .collect();
+        let framebuffer = Framebuffer::new(width, height, pixels);
+
+        let path = std::env::temp_dir().join(format!("framebuffer-{}.pfm", std::process::id()));
+        framebuffer.write_pfm(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let data = &bytes[header.len()..];
+        assert_eq!(data.len(), (width * height * 3 * 4) as usize);
+
+        let channels: Vec<f32> = data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        for (i, color) in channels.chunks_exact(3).enumerate() {
+            let (x, y) = (i as u32 % width, height - 1 - i as u32 / width);
+            let expected = framebuffer.get(x, y);
+            assert_eq!(color, [expected.red, expected.green, expected.blue]);
+        }
+        // The first pixel in the file is the bottom left one.
+        assert_eq!(channels[0], 3.);
+    }
+}
@@ -2,10 +2,20 @@ use std::ops::{Add, Range};
 
 use bevy_math::Vec3;
 
+pub mod animation;
+pub mod aov;
 pub mod camera;
+pub mod checkpoint;
+pub mod framebuffer;
 pub mod material;
 pub mod mesh;
+pub mod obj;
+pub mod progress;
+pub mod sampler;
+pub mod scene;
 pub mod texture;
+pub mod tile;
+pub mod tonemap;
 pub mod utils;
 
 #[derive(Default)]
@@ -62,6 +72,7 @@ impl Add<f32> for Interval {
 
 impl Interval {
     const UNIVERSE: Self = Self(f32::NEG_INFINITY..f32::INFINITY);
+    const EMPTY: Self = Self(f32::INFINITY..f32::NEG_INFINITY);
 
     pub fn start(&self) -> f32 {
         self.0.start
@@ -1,20 +1,43 @@
 use core::f32;
-use std::{fs::File, sync::Arc};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bevy_color::Color;
-use bevy_math::Vec3;
+use bevy_math::{Affine3A, Quat, Vec3};
 use clap::{Parser, ValueEnum};
-use image::{ImageFormat, ImageResult};
-use rand::prelude::*;
+use image::ImageResult;
+use indicatif::ProgressBar;
+use rand::{prelude::*, rngs::SmallRng};
 use ray_tracing::{
-    camera::{Camera, CameraConfig},
+    animation::{frame_path, Animation},
+    aov::{Aov, AovBuffers},
+    camera::{Camera, CameraConfig, Integrator, Shutter, SHUTTER_RANGE},
+    checkpoint::Checkpoint,
+    framebuffer::{ExrPrecision, Framebuffer},
     material::{Dielectric, DiffuseLight, Lambertian, Metal},
-    mesh::{Bvh, ConstantMedium, Cube, Quad, RotateY, Sphere, Translate, World},
+    mesh::{
+        Bvh, BvhStrategy, ConstantMedium, Cube, Instance, Mesh, Quad, RotateY, Sphere, Translate,
+        World,
+    },
+    sampler::SamplerKind,
+    scene,
     texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidTexture},
+    tile::TileOrder,
+    tonemap::ToneMapping,
     utils::random_vec,
 };
 
+/// Seeds the random layouts and textures of the built-in scenes, which
+/// stay the same from run to run.
+const SCENE_SEED: u64 = 0;
+
 #[derive(ValueEnum, Clone)]
+#[allow(clippy::enum_variant_names)]
 enum Scene {
     BouncingSpheres,
     CheckeredSpheres,
@@ -34,7 +57,6 @@ impl Scene {
                 width: 800,
                 height: 600,
                 samples_per_pixel: 100,
-                max_depth: 50,
                 lookfrom: Vec3::new(13., 2., 3.),
                 lookat: Vec3::ZERO,
                 vup: Vec3::Y,
@@ -47,7 +69,6 @@ impl Scene {
                 width: 800,
                 height: 600,
                 samples_per_pixel: 100,
-                max_depth: 50,
                 vfov: 20.,
                 lookfrom: Vec3::new(13., 2., 3.),
                 lookat: Vec3::ZERO,
@@ -59,7 +80,6 @@ impl Scene {
                 width: 800,
                 height: 600,
                 samples_per_pixel: 100,
-                max_depth: 50,
                 lookfrom: Vec3::new(0., 0., 12.),
                 lookat: Vec3::ZERO,
                 vup: Vec3::Y,
@@ -70,7 +90,6 @@ impl Scene {
                 width: 800,
                 height: 600,
                 samples_per_pixel: 100,
-                max_depth: 50,
                 vfov: 20.,
                 lookfrom: Vec3::new(13., 2., 3.),
                 lookat: Vec3::ZERO,
@@ -82,7 +101,6 @@ impl Scene {
                 width: 800,
                 height: 800,
                 samples_per_pixel: 100,
-                max_depth: 50,
                 vfov: 80.,
                 lookfrom: Vec3::Z * 9.,
                 lookat: Vec3::ZERO,
@@ -94,7 +112,6 @@ impl Scene {
                 width: 800,
                 height: 600,
                 samples_per_pixel: 500,
-                max_depth: 50,
                 background: Color::BLACK,
                 lookfrom: Vec3::new(26., 3., 6.),
                 lookat: Vec3::Y * 2.,
@@ -106,7 +123,6 @@ impl Scene {
                 width: 600,
                 height: 600,
                 samples_per_pixel: 1000,
-                max_depth: 50,
                 background: Color::BLACK,
                 lookfrom: Vec3::new(278.0, 278.0, -800.0),
                 lookat: Vec3::new(278.0, 278.0, 0.0),
@@ -119,7 +135,6 @@ impl Scene {
                 width: 600,
                 height: 600,
                 samples_per_pixel: 200,
-                max_depth: 50,
                 background: Color::BLACK,
                 lookfrom: Vec3::new(278.0, 278.0, -800.0),
                 lookat: Vec3::new(278.0, 278.0, 0.0),
@@ -132,7 +147,6 @@ impl Scene {
                 width: 800,
                 height: 800,
                 samples_per_pixel: 10_000,
-                max_depth: 40,
                 background: Color::BLACK,
                 vfov: 40.,
                 lookfrom: Vec3::new(478., 278., -600.),
@@ -171,10 +185,9 @@ impl Scene {
             }),
         ));
 
-        let mut rng = rand::thread_rng();
+        let mut rng = SmallRng::seed_from_u64(SCENE_SEED);
 
         (-11..11)
-            .into_iter()
             .flat_map(|x| (-11..11).map(move |y| [x as f32, y as f32]))
             .for_each(|[x, y]| {
                 let center = Vec3::new(x + 0.9 * rng.gen::<f32>(), 0.2, y + 0.9 * rng.gen::<f32>());
@@ -255,7 +268,7 @@ impl Scene {
     fn perlin_spheres() -> ImageResult<World> {
         let mut world = World::new();
 
-        let perlin = NoiseTexture::<256>::new(rand::thread_rng(), 4.);
+        let perlin = NoiseTexture::<256>::new(SmallRng::seed_from_u64(SCENE_SEED), 4.);
         let perlin_surface = Arc::new(Lambertian::from(perlin));
 
         world.push(Sphere::stationary(
@@ -306,7 +319,7 @@ impl Scene {
 
     fn simple_light() -> ImageResult<World> {
         let pertext = Arc::new(Lambertian::from(NoiseTexture::<256>::new(
-            rand::thread_rng(),
+            SmallRng::seed_from_u64(SCENE_SEED),
             4.,
         )));
         let difflight = Arc::new(DiffuseLight::rgb(4., 4., 4.));
@@ -314,13 +327,13 @@ impl Scene {
         let mut world = World::new();
         world.push(Sphere::stationary(Vec3::Y * -1000., 1000., pertext.clone()));
         world.push(Sphere::stationary(Vec3::Y * 2., 2., pertext));
-        world.push(Quad::new(
+        world.push_light(Quad::new(
             Vec3::new(3., 1., -2.),
             Vec3::X * 2.,
             Vec3::Y * 2.,
             difflight.clone(),
         ));
-        world.push(Sphere::stationary(Vec3::Y * 7., 2., difflight));
+        world.push_light(Sphere::stationary(Vec3::Y * 7., 2., difflight));
 
         Ok(world)
     }
@@ -341,7 +354,7 @@ impl Scene {
             Vec3::Z * 555.,
             Lambertian::rgb(0.65, 0.05, 0.05),
         ));
-        world.push(Quad::new(
+        world.push_light(Quad::new(
             Vec3::new(343., 554., 332.),
             Vec3::X * -130.0,
             Vec3::Z * -105.0,
@@ -400,7 +413,7 @@ impl Scene {
             Vec3::Z * 555.,
             Lambertian::rgb(0.65, 0.05, 0.05),
         ));
-        world.push(Quad::new(
+        world.push_light(Quad::new(
             Vec3::new(113., 554., 127.),
             Vec3::X * 330.0,
             Vec3::Z * 305.0,
@@ -448,10 +461,15 @@ impl Scene {
 
     #[allow(unused)]
     fn final_scene() -> ImageResult<World> {
+        // Every ground box and small sphere is an instance of one prototype.
         let mut boxes1 = World::new();
-        let ground = Arc::new(Lambertian::rgb(0.48, 0.83, 0.53));
+        let ground: Arc<dyn Mesh + Sync + Send> = Arc::new(Cube::new(
+            Vec3::ZERO,
+            Vec3::ONE,
+            Lambertian::rgb(0.48, 0.83, 0.53),
+        ));
 
-        let mut rng = rand::thread_rng();
+        let mut rng = SmallRng::seed_from_u64(SCENE_SEED);
 
         let boxes_per_side = 20;
         for i in 0..boxes_per_side {
@@ -460,13 +478,14 @@ impl Scene {
                 let x0 = -1000. + i as f32 * w;
                 let z0 = -1000. + j as f32 * w;
                 let y0 = 0.;
-                let x1 = x0 + w;
                 let y1 = rng.gen_range(0.0..=100.);
-                let z1 = z0 + w;
-                boxes1.push(Cube::new(
-                    Vec3::new(x0, y0, z0),
-                    Vec3::new(x1, y1, z1),
+                boxes1.push(Instance::new(
                     ground.clone(),
+                    Affine3A::from_scale_rotation_translation(
+                        Vec3::new(w, y1 - y0, w),
+                        Quat::IDENTITY,
+                        Vec3::new(x0, y0, z0),
+                    ),
                 ));
             }
         }
@@ -474,7 +493,7 @@ impl Scene {
         let mut world = World::new();
         world.push(Bvh::from(boxes1));
 
-        world.push(Quad::new(
+        world.push_light(Quad::new(
             Vec3::new(123., 554., 147.),
             Vec3::X * 300.,
             Vec3::Z * 265.,
@@ -518,16 +537,20 @@ impl Scene {
         ));
 
         let mut boxes2 = World::new();
+        let white: Arc<dyn Mesh + Sync + Send> = Arc::new(Sphere::stationary(
+            Vec3::ZERO,
+            10.,
+            Lambertian::rgb(0.73, 0.73, 0.73),
+        ));
         let ns = 1000;
-        for i in 0..ns {
-            boxes2.push(Sphere::stationary(
-                Vec3::new(
+        for _ in 0..ns {
+            boxes2.push(Instance::new(
+                white.clone(),
+                Affine3A::from_translation(Vec3::new(
                     rng.gen_range(0.0..165.),
                     rng.gen_range(0.0..165.),
                     rng.gen_range(0.0..165.),
-                ),
-                10.,
-                Lambertian::rgb(0.73, 0.73, 0.73),
+                )),
             ));
         }
 
@@ -542,44 +565,379 @@ impl Scene {
 
 #[derive(Parser)]
 struct Cli {
+    /// Samples per pixel, or their average with adaptive sampling.
     #[arg(short, long)]
     samples: Option<usize>,
 
+    /// Relative noise level at which adaptive sampling stops sampling a
+    /// pixel; unset takes the same number of samples everywhere.
+    #[arg(long)]
+    noise_threshold: Option<f32>,
+
+    /// Keep adding passes for this many seconds instead of stopping at a
+    /// sample count.
+    #[arg(long)]
+    time_budget: Option<f64>,
+
     #[arg(short, long)]
     width: Option<u32>,
 
     #[arg(long)]
     height: Option<u32>,
 
+    /// Safety limit on bounces; Russian roulette normally ends paths long before.
     #[arg(short, long)]
     depth: Option<usize>,
 
+    /// Vertical field of view in degrees, overriding that of every camera
+    /// keyframe too.
     #[arg(short, long)]
     vfov: Option<f32>,
 
-    scene: Scene,
+    #[arg(long)]
+    roulette_depth: Option<usize>,
+
+    #[arg(short, long, value_enum)]
+    integrator: Option<Integrator>,
+
+    /// Source of the numbers placing the samples of every pixel.
+    #[arg(long, value_enum)]
+    sampler: Option<SamplerKind>,
+
+    /// Seed of the sample generators; equal seeds give identical images.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Time at which the shutter opens, between 0 and 1.
+    #[arg(long, allow_hyphen_values = true, value_parser = shutter_time)]
+    shutter_open: Option<f32>,
+
+    /// Time at which the shutter closes, between 0 and 1.
+    #[arg(long, allow_hyphen_values = true, value_parser = shutter_time)]
+    shutter_close: Option<f32>,
+
+    /// Shutter shape: `box`, `triangle` or comma-separated openness values.
+    #[arg(long)]
+    shutter: Option<Shutter>,
+
+    /// Exposure compensation in stops, applied to 8-bit output.
+    #[arg(short, long, allow_hyphen_values = true)]
+    exposure: Option<f32>,
+
+    /// Temperature in kelvin of the light that should appear white in 8-bit output.
+    #[arg(long)]
+    white_balance: Option<f32>,
+
+    /// Operator mapping radiance into the 8-bit output range.
+    #[arg(short, long, value_enum)]
+    tone_mapping: Option<ToneMapping>,
+
+    /// Side of the square tiles the image is rendered in, in pixels.
+    #[arg(long)]
+    tile_size: Option<u32>,
+
+    /// Order in which tiles are rendered.
+    #[arg(long, value_enum)]
+    tile_order: Option<TileOrder>,
+
+    /// Strategy used to build the bounding volume hierarchy over the scene.
+    #[arg(long, value_enum, default_value_t)]
+    bvh: BvhStrategy,
+
+    #[arg(required_unless_present = "file")]
+    scene: Option<Scene>,
+
+    /// Render a TOML scene description instead of a built-in scene.
+    #[arg(short, long, conflicts_with = "scene")]
+    file: Option<PathBuf>,
+
+    /// Output image; `.exr` and `.pfm` keep the full dynamic range, other
+    /// extensions are written as 8-bit sRGB. Animation frames replace a run of
+    /// `#` in the file name with the frame number, or append it to the name.
+    #[arg(short, long, default_value = "image.png")]
+    output: PathBuf,
+
+    /// First frame to render, enabling animation mode. Time is measured in frames.
+    #[arg(long)]
+    start_frame: Option<u32>,
+
+    /// Frame after the last one to render, enabling animation mode.
+    #[arg(long)]
+    end_frame: Option<u32>,
+
+    /// Render in passes of doubling sample count, writing the output after
+    /// each of them so that it can be stopped early.
+    #[arg(long)]
+    progressive: bool,
+
+    /// Write the output only every this many progressive passes. Without
+    /// it or `--snapshot-interval`, every pass writes the output.
+    #[arg(long, requires = "progressive")]
+    snapshot_passes: Option<u32>,
+
+    /// Write the output at most this often during progressive rendering,
+    /// in seconds, in addition to every `--snapshot-passes` passes if given.
+    #[arg(long, requires = "progressive")]
+    snapshot_interval: Option<f64>,
+
+    /// Periodically save the samples taken so far to this file, from which
+    /// `--resume` continues. Animation frames name their checkpoints like
+    /// their images.
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Least time between checkpoints, in seconds.
+    #[arg(long, requires = "checkpoint", default_value_t = 60.)]
+    checkpoint_interval: f64,
+
+    /// Continue from the checkpoint if there is one, giving the same image
+    /// as a render that was never interrupted. Checkpoints of renders with a
+    /// time budget only continue with a time budget.
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+
+    /// Channel type of OpenEXR output.
+    #[arg(long, value_enum, default_value_t)]
+    exr_precision: ExrPrecision,
+
+    /// Auxiliary passes of what camera rays hit first, comma-separated.
+    /// OpenEXR output stores them as layers of the image, other formats
+    /// write them next to it as `<name>.<pass>.<extension>`.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    aov: Vec<Aov>,
+}
+
+/// Parses a shutter time, which must lie within [`SHUTTER_RANGE`].
+fn shutter_time(s: &str) -> Result<f32, String> {
+    let time: f32 = s.parse().map_err(|err| format!("{err}"))?;
+    if SHUTTER_RANGE.contains(&time) {
+        Ok(time)
+    } else {
+        Err(format!("{time} is outside [0, 1]"))
+    }
 }
 
-fn main() -> ImageResult<()> {
+fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
 
-    let config = cli.scene.camera_config();
-    let camera = Camera::new(CameraConfig {
+    // Checkpoints are tied to the scene file, or the name of a built-in scene.
+    let (config, world, animation, description) = match (&cli.file, &cli.scene) {
+        (Some(path), _) => {
+            let scene = scene::load(path)?;
+            let description = fs::read(path)?;
+            (scene.camera, scene.world, scene.animation, description)
+        }
+        (None, Some(scene)) => {
+            let name = scene.to_possible_value().expect("scenes are never skipped");
+            let description = name.get_name().as_bytes().to_vec();
+            (scene.camera_config(), scene.world()?, None, description)
+        }
+        (None, None) => unreachable!("clap requires a scene or a file"),
+    };
+
+    let config = CameraConfig {
         width: cli.width.unwrap_or(config.width),
         height: cli.height.unwrap_or(config.height),
         samples_per_pixel: cli.samples.unwrap_or(config.samples_per_pixel),
+        noise_threshold: cli.noise_threshold.or(config.noise_threshold),
+        time_budget: cli
+            .time_budget
+            .map(Duration::from_secs_f64)
+            .or(config.time_budget),
         max_depth: cli.depth.unwrap_or(config.max_depth),
+        roulette_depth: cli.roulette_depth.unwrap_or(config.roulette_depth),
         vfov: cli.vfov.unwrap_or(config.vfov),
+        integrator: cli.integrator.unwrap_or(config.integrator),
+        sampler: cli.sampler.unwrap_or(config.sampler),
+        seed: cli.seed.unwrap_or(config.seed),
+        shutter_open: cli.shutter_open.unwrap_or(config.shutter_open),
+        shutter_close: cli.shutter_close.unwrap_or(config.shutter_close),
+        shutter: cli.shutter.clone().unwrap_or(config.shutter),
+        exposure: cli.exposure.unwrap_or(config.exposure),
+        white_balance: cli.white_balance.unwrap_or(config.white_balance),
+        tone_mapping: cli.tone_mapping.unwrap_or(config.tone_mapping),
+        tile_size: cli.tile_size.unwrap_or(config.tile_size),
+        tile_order: cli.tile_order.unwrap_or(config.tile_order),
         ..config
+    };
+    if config.shutter_close < config.shutter_open {
+        let (open, close) = (config.shutter_open, config.shutter_close);
+        return Err(format!("shutter closes at {close} before it opens at {open}").into());
+    }
+
+    let animation = match (animation, cli.start_frame, cli.end_frame) {
+        (None, None, None) => None,
+        (animation, start, end) => {
+            let mut animation = animation.unwrap_or_else(|| Animation::new(0..1));
+            animation.frames =
+                start.unwrap_or(animation.frames.start)..end.unwrap_or(animation.frames.end);
+            if let Some(vfov) = cli.vfov {
+                animation = animation.with_vfov(vfov);
+            }
+            Some(animation)
+        }
+    };
+
+    let lights = world.lights();
+    let bvh_world = Bvh::new(&world, cli.bvh);
+
+    let Some(animation) = animation else {
+        let checkpoint = cli.checkpoint.as_deref();
+        return render(
+            &cli,
+            config,
+            &bvh_world,
+            &lights,
+            &description,
+            &cli.output,
+            checkpoint,
+        );
+    };
+
+    for frame in animation.frames.clone() {
+        let config = animation.frame_config(&config, frame);
+        let path = frame_path(&cli.output, frame);
+        let checkpoint = cli.checkpoint.as_ref().map(|path| frame_path(path, frame));
+        render(
+            &cli,
+            config,
+            &bvh_world,
+            &lights,
+            &description,
+            &path,
+            checkpoint.as_deref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Renders one image to `path`, along with the snapshots and checkpoints
+/// asked for. Checkpoints only resume renders of the scene `description`.
+fn render(
+    cli: &Cli,
+    config: CameraConfig,
+    world: &Bvh,
+    lights: &World,
+    description: &[u8],
+    path: &Path,
+    checkpoint_path: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let time_budget = config.time_budget;
+    if !cli.progressive && checkpoint_path.is_none() && time_budget.is_none() {
+        let camera = Camera::new(config).with_progress(ProgressBar::new(0));
+        let framebuffer = camera.render(world, lights);
+        let aovs = (!cli.aov.is_empty()).then(|| camera.render_aovs(world));
+        return write_image(cli, &camera, &framebuffer, aovs.as_ref(), path);
+    }
+
+    let checkpoint = match checkpoint_path {
+        Some(checkpoint_path) if cli.resume && checkpoint_path.exists() => {
+            Checkpoint::read(checkpoint_path, &config, description)?
+        }
+        Some(checkpoint_path) if cli.resume => {
+            eprintln!(
+                "no checkpoint at {}, starting from scratch",
+                checkpoint_path.display()
+            );
+            Checkpoint::new(&config).with_scene(description)
+        }
+        _ => Checkpoint::new(&config).with_scene(description),
+    };
+    let camera = Camera::new(config).with_progress(ProgressBar::new(0));
+
+    let snapshot_interval = cli.snapshot_interval.map(Duration::from_secs_f64);
+    let checkpoint_interval = Duration::from_secs_f64(cli.checkpoint_interval);
+    let mut passes = 0;
+    let mut last_snapshot = Instant::now();
+    let mut last_checkpoint = Instant::now();
+    let checkpoint = camera.render_progressive(world, lights, checkpoint, |checkpoint| {
+        passes += 1;
+
+        // Failing to save progress is no reason to throw the render away.
+        let snapshot_due = match (cli.snapshot_passes, snapshot_interval) {
+            (None, None) => true,
+            (every, interval) => {
+                every.is_some_and(|every| passes % every.max(1) == 0)
+                    || interval.is_some_and(|interval| last_snapshot.elapsed() >= interval)
+            }
+        };
+        if cli.progressive && snapshot_due {
+            last_snapshot = Instant::now();
+            let framebuffer = checkpoint.framebuffer();
+            if let Err(error) = write_image(cli, &camera, &framebuffer, None, path) {
+                eprintln!("failed to write snapshot to {}: {error}", path.display());
+            }
+        }
+
+        if let Some(checkpoint_path) = checkpoint_path {
+            if last_checkpoint.elapsed() >= checkpoint_interval {
+                last_checkpoint = Instant::now();
+                if let Err(error) = checkpoint.write(checkpoint_path) {
+                    eprintln!("failed to write checkpoint: {error}");
+                }
+            }
+        }
     });
 
-    let world = cli.scene.world()?;
-    let bvh_world = Bvh::from(&world);
+    if let Some(checkpoint_path) = checkpoint_path {
+        checkpoint.write(checkpoint_path)?;
+    }
+    if time_budget.is_some() {
+        println!(
+            "{}: {:.1} samples per pixel",
+            path.display(),
+            checkpoint.mean_samples()
+        );
+    }
+    let aovs = (!cli.aov.is_empty()).then(|| camera.render_aovs(world));
+    write_image(cli, &camera, &checkpoint.framebuffer(), aovs.as_ref(), path)
+}
 
-    let image = camera.render(&bvh_world);
+/// Writes `framebuffer` to `path`, along with the passes in `aovs` that the
+/// command line asks for.
+fn write_image(
+    cli: &Cli,
+    camera: &Camera,
+    framebuffer: &Framebuffer,
+    aovs: Option<&AovBuffers>,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let extension = extension.map(str::to_ascii_lowercase);
+
+    match (extension.as_deref(), aovs) {
+        (Some("exr"), Some(aovs)) => {
+            aovs.write_exr(path, framebuffer, &cli.aov, cli.exr_precision)?;
+            return Ok(());
+        }
+        (Some("exr"), None) => framebuffer.write_exr(path, cli.exr_precision)?,
+        (Some("pfm"), _) => framebuffer.write_pfm(path)?,
+        _ => camera.tone_map(framebuffer).to_srgb_image().save(path)?,
+    }
 
-    let mut file = File::create("image.png")?;
-    image.write_to(&mut file, ImageFormat::Png)?;
+    let Some(aovs) = aovs else {
+        return Ok(());
+    };
+    for &aov in &cli.aov {
+        let path = aov_path(path, aov);
+        match extension.as_deref() {
+            Some("pfm") => aovs.framebuffer(aov).write_pfm(&path)?,
+            _ => aovs.to_image(aov).save(&path)?,
+        }
+    }
 
     Ok(())
 }
+
+/// Inserts the name of `aov` before the extension of `path`.
+fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(aov.name());
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
@@ -2,11 +2,12 @@ use std::sync::Arc;
 
 use bevy_color::{Color, LinearRgba};
 use bevy_math::{Vec2, Vec3};
+use rand::{Rng, RngCore};
 
 use crate::{
     mesh::Hit,
     texture::{SolidTexture, Texture},
-    utils::{near_zero, random_unit_vec},
+    utils::{from_local, random_cosine_direction, random_unit_vec, PI},
     Ray,
 };
 
@@ -28,26 +29,69 @@ fn refract(uv: Vec3, n: Vec3, etai_over_etat: f32) -> Vec3 {
 }
 
 pub trait Material {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter>;
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<Scatter>;
+
+    /// BSDF multiplied by the cosine term for light leaving along `-ray.direction`
+    /// after arriving from `direction`. Specular materials return black.
+    fn eval(&self, _ray: &Ray, _hit: &Hit, _direction: Vec3) -> LinearRgba {
+        LinearRgba::BLACK
+    }
+
+    /// Solid angle density of [`Material::scatter`] sampling `direction`.
+    /// Specular materials return 0.
+    fn pdf(&self, _ray: &Ray, _hit: &Hit, _direction: Vec3) -> f32 {
+        0.
+    }
 
     fn emitted(&self, _uv: Vec2, _point: Vec3) -> LinearRgba {
         LinearRgba::BLACK
     }
+
+    /// Base color of the surface, as written to the albedo output pass.
+    fn albedo(&self, _uv: Vec2, _point: Vec3) -> LinearRgba {
+        LinearRgba::BLACK
+    }
+
+    /// Address of the material, telling materials shared between meshes apart
+    /// from merely equal ones.
+    fn id(&self) -> usize {
+        self as *const Self as *const () as usize
+    }
 }
 
-impl<T: Material> Material for Arc<T> {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
-        self.as_ref().scatter(ray, hit)
+impl<T: Material + ?Sized> Material for Arc<T> {
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<Scatter> {
+        self.as_ref().scatter(ray, hit, rng)
+    }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: Vec3) -> LinearRgba {
+        self.as_ref().eval(ray, hit, direction)
+    }
+
+    fn pdf(&self, ray: &Ray, hit: &Hit, direction: Vec3) -> f32 {
+        self.as_ref().pdf(ray, hit, direction)
     }
 
     fn emitted(&self, uv: Vec2, point: Vec3) -> LinearRgba {
         self.as_ref().emitted(uv, point)
     }
+
+    fn albedo(&self, uv: Vec2, point: Vec3) -> LinearRgba {
+        self.as_ref().albedo(uv, point)
+    }
+
+    fn id(&self) -> usize {
+        self.as_ref().id()
+    }
 }
 
 pub struct Scatter {
+    /// [`Material::eval`] of the scattered direction divided by its density.
     pub attenuation: LinearRgba,
     pub scattered: Ray,
+    /// Density the scattered direction was sampled with, or `None` for
+    /// specular scattering which cannot be evaluated for arbitrary directions.
+    pub pdf: Option<f32>,
 }
 
 #[derive(Default)]
@@ -68,13 +112,27 @@ impl From<Color> for Lambertian<SolidTexture> {
 }
 
 impl<T: Texture> Material for Lambertian<T> {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
-        let scatter_dir = self.scatter_direction(hit);
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<Scatter> {
+        let scatter_dir = from_local(hit.normal, random_cosine_direction(rng));
         Some(Scatter {
             attenuation: self.texture.value(hit.uv, hit.point),
+            pdf: Some(self.pdf(ray, hit, scatter_dir)),
             scattered: Ray::new(hit.point, scatter_dir, ray.time),
         })
     }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: Vec3) -> LinearRgba {
+        self.texture.value(hit.uv, hit.point) * self.pdf(ray, hit, direction)
+    }
+
+    fn pdf(&self, _ray: &Ray, hit: &Hit, direction: Vec3) -> f32 {
+        let cos_theta = hit.normal.dot(direction.normalize());
+        cos_theta.max(0.) / PI
+    }
+
+    fn albedo(&self, uv: Vec2, point: Vec3) -> LinearRgba {
+        self.texture.value(uv, point)
+    }
 }
 
 impl Lambertian<SolidTexture> {
@@ -83,18 +141,6 @@ impl Lambertian<SolidTexture> {
     }
 }
 
-impl<T: Texture> Lambertian<T> {
-    fn scatter_direction(&self, hit: &Hit) -> Vec3 {
-        let rng = rand::thread_rng();
-        let dir = hit.normal + random_unit_vec(rng);
-        if near_zero(dir) {
-            hit.normal
-        } else {
-            dir
-        }
-    }
-}
-
 #[derive(Default)]
 pub struct Metal<T: Texture> {
     pub texture: T,
@@ -102,20 +148,58 @@ pub struct Metal<T: Texture> {
 }
 
 impl<T: Texture> Material for Metal<T> {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
-        let rng = rand::thread_rng();
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<Scatter> {
         let reflected =
             reflect(ray.direction, hit.normal) + (self.roughness * random_unit_vec(rng));
         let scattered = Ray::new(hit.point, reflected, ray.time);
         if scattered.direction.dot(hit.normal) > 0. {
             Some(Scatter {
                 attenuation: self.texture.value(hit.uv, hit.point),
+                pdf: (self.roughness > 0.).then(|| self.pdf(ray, hit, reflected)),
                 scattered,
             })
         } else {
             None
         }
     }
+
+    fn eval(&self, ray: &Ray, hit: &Hit, direction: Vec3) -> LinearRgba {
+        if direction.dot(hit.normal) > 0. {
+            self.texture.value(hit.uv, hit.point) * self.pdf(ray, hit, direction)
+        } else {
+            LinearRgba::BLACK
+        }
+    }
+
+    /// The fuzzed reflection is a uniform point on a sphere of radius
+    /// `roughness` around the mirror direction, so the density of a direction
+    /// sums the area density of every point where it pierces that sphere,
+    /// converted to solid angle.
+    fn pdf(&self, ray: &Ray, hit: &Hit, direction: Vec3) -> f32 {
+        if self.roughness <= 0. {
+            return 0.;
+        }
+
+        let reflected = reflect(ray.direction, hit.normal);
+        let direction = direction.normalize();
+        let projection = direction.dot(reflected);
+        let discriminant =
+            projection * projection - reflected.length_squared() + self.roughness * self.roughness;
+        if discriminant <= 0. {
+            return 0.;
+        }
+
+        let sqrtd = discriminant.sqrt();
+        [projection - sqrtd, projection + sqrtd]
+            .into_iter()
+            .filter(|&t| t > 0.)
+            .map(|t| t * t / (4. * PI * self.roughness * sqrtd))
+            .sum()
+    }
+
+    fn albedo(&self, uv: Vec2, point: Vec3) -> LinearRgba {
+        self.texture.value(uv, point)
+    }
 }
 
 impl<T: Texture> Metal<T> {
@@ -147,7 +231,7 @@ impl Default for Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, hit: &Hit) -> Option<Scatter> {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<Scatter> {
         let ri = if hit.front_face {
             1. / self.refraction_index
         } else {
@@ -159,7 +243,7 @@ impl Material for Dielectric {
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
 
         let cannot_refact = ri * sin_theta > 1.;
-        let dir = if cannot_refact || reflectance(cos_theta, ri) > rand::random::<f32>() {
+        let dir = if cannot_refact || reflectance(cos_theta, ri) > rng.gen::<f32>() {
             reflect(unit_dir, hit.normal)
         } else {
             refract(unit_dir, hit.normal, ri)
@@ -168,8 +252,13 @@ impl Material for Dielectric {
         Some(Scatter {
             attenuation: LinearRgba::WHITE,
             scattered: Ray::new(hit.point, dir, r_in.time),
+            pdf: None,
         })
     }
+
+    fn albedo(&self, _uv: Vec2, _point: Vec3) -> LinearRgba {
+        LinearRgba::WHITE
+    }
 }
 
 impl Dielectric {
@@ -196,13 +285,17 @@ impl From<Color> for DiffuseLight<SolidTexture> {
 }
 
 impl<T: Texture> Material for DiffuseLight<T> {
-    fn scatter(&self, _ray: &Ray, _hit: &Hit) -> Option<Scatter> {
+    fn scatter(&self, _ray: &Ray, _hit: &Hit, _rng: &mut dyn RngCore) -> Option<Scatter> {
         None
     }
 
     fn emitted(&self, uv: Vec2, point: Vec3) -> LinearRgba {
         self.texture.value(uv, point)
     }
+
+    fn albedo(&self, uv: Vec2, point: Vec3) -> LinearRgba {
+        self.texture.value(uv, point)
+    }
 }
 
 impl DiffuseLight<SolidTexture> {
@@ -229,14 +322,25 @@ impl From<Color> for Isotropic<SolidTexture> {
 }
 
 impl<T: Texture> Material for Isotropic<T> {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
-        let rng = rand::thread_rng();
-
+    fn scatter(&self, ray: &Ray, hit: &Hit, rng: &mut dyn RngCore) -> Option<Scatter> {
         Some(Scatter {
             scattered: Ray::new(hit.point, random_unit_vec(rng), ray.time),
             attenuation: self.texture.value(hit.uv, hit.point),
+            pdf: Some(1. / (4. * PI)),
         })
     }
+
+    fn eval(&self, _ray: &Ray, hit: &Hit, _direction: Vec3) -> LinearRgba {
+        self.texture.value(hit.uv, hit.point) / (4. * PI)
+    }
+
+    fn pdf(&self, _ray: &Ray, _hit: &Hit, _direction: Vec3) -> f32 {
+        1. / (4. * PI)
+    }
+
+    fn albedo(&self, uv: Vec2, point: Vec3) -> LinearRgba {
+        self.texture.value(uv, point)
+    }
 }
 
 impl Isotropic<SolidTexture> {
@@ -1,23 +1,56 @@
 use core::f32;
 use std::{
+    array,
     ops::{Add, Index},
     sync::Arc,
 };
 
 use bevy_color::Color;
-use bevy_math::{Vec2, Vec3};
+use bevy_math::{Affine3A, Mat3A, Quat, Vec2, Vec3, Vec3A};
+use clap::ValueEnum;
+use rand::{seq::SliceRandom as _, Rng, RngCore};
+use serde::Deserialize;
 
 use crate::{
     material::{Isotropic, Material},
     texture::{SolidTexture, Texture},
-    utils::{degrees_to_radians, PI},
+    utils::{degrees_to_radians, from_local, random_to_sphere, PI},
     Interval, Ray,
 };
 
 pub trait Mesh {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit>;
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>>;
 
     fn bounding_box(&self) -> &Aabb;
+
+    /// Solid angle density of [`Mesh::random`] generating `ray.direction` from
+    /// `ray.origin`. Meshes that cannot be sampled return 0.
+    fn pdf_value(&self, _ray: &Ray) -> f32 {
+        0.
+    }
+
+    /// Direction from `origin` towards a random point on the mesh.
+    fn random(&self, _origin: Vec3, _time: f32, _rng: &mut dyn RngCore) -> Vec3 {
+        Vec3::X
+    }
+}
+
+impl<T: Mesh + ?Sized> Mesh for Arc<T> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        self.as_ref().hit(ray, ray_t, rng)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.as_ref().pdf_value(ray)
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        self.as_ref().random(origin, time, rng)
+    }
 }
 
 pub struct Hit<'a> {
@@ -27,6 +60,9 @@ pub struct Hit<'a> {
     pub front_face: bool,
     pub material: &'a dyn Material,
     pub uv: Vec2,
+    /// Address of the outermost mesh of the world that was hit, telling
+    /// objects apart. Left at 0 by the meshes themselves.
+    pub object_id: usize,
 }
 
 impl<'a> Hit<'a> {
@@ -47,12 +83,19 @@ impl<'a> Hit<'a> {
             front_face: true,
             material,
             uv,
+            object_id: 0,
         }
     }
 }
 
+/// [`Hit::object_id`] of the meshes inside `mesh`.
+fn object_id(mesh: &Arc<dyn Mesh + Sync + Send>) -> usize {
+    Arc::as_ptr(mesh) as *const () as usize
+}
+
 pub struct World {
     meshes: Vec<Arc<dyn Mesh + Sync + Send>>,
+    lights: Vec<Arc<dyn Mesh + Sync + Send>>,
     bbox: Aabb,
 }
 
@@ -63,7 +106,7 @@ impl AsRef<[Arc<dyn Mesh + Sync + Send>]> for World {
 }
 
 impl Mesh for World {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
         let mut current_hit: Option<Hit> = None;
 
         for mesh in &self.meshes {
@@ -73,8 +116,11 @@ impl Mesh for World {
                 .unwrap_or(ray_t.end());
             let t = (ray_t.start()..tmax).into();
 
-            if let Some(hit) = mesh.hit(ray, &t) {
-                current_hit = Some(hit);
+            if let Some(hit) = mesh.hit(ray, &t, rng) {
+                current_hit = Some(Hit {
+                    object_id: object_id(mesh),
+                    ..hit
+                });
             }
         }
 
@@ -84,19 +130,83 @@ impl Mesh for World {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        if self.meshes.is_empty() {
+            return 0.;
+        }
+
+        let sum: f32 = self.meshes.iter().map(|mesh| mesh.pdf_value(ray)).sum();
+        sum / self.meshes.len() as f32
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        self.meshes
+            .choose(rng)
+            .map_or(Vec3::X, |mesh| mesh.random(origin, time, rng))
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self {
         Self {
             meshes: Vec::new(),
+            lights: Vec::new(),
             bbox: Aabb::default(),
         }
     }
 
     pub fn push(&mut self, mesh: impl Mesh + Sync + Send + 'static) {
-        self.bbox = self.bbox.merge(&mesh.bounding_box());
-        self.meshes.push(Arc::new(mesh));
+        self.push_shared(Arc::new(mesh));
+    }
+
+    /// Pushes an emissive mesh which is also sampled directly by the camera.
+    pub fn push_light(&mut self, mesh: impl Mesh + Sync + Send + 'static) {
+        let mesh = Arc::new(mesh);
+        self.lights.push(mesh.clone());
+        self.push_shared(mesh);
+    }
+
+    /// Samples `light` directly without adding it to the scene, for emitters
+    /// that are already part of another mesh, such as a transformed model.
+    pub fn push_light_source(&mut self, light: impl Mesh + Sync + Send + 'static) {
+        self.lights.push(Arc::new(light));
+    }
+
+    fn push_shared(&mut self, mesh: Arc<dyn Mesh + Sync + Send>) {
+        self.bbox = self.bbox.merge(mesh.bounding_box());
+        self.meshes.push(mesh);
+    }
+
+    /// Moves every mesh of `other` into this world, keeping track of its lights.
+    pub fn append(&mut self, other: World) {
+        self.lights.extend(other.lights);
+        for mesh in other.meshes {
+            self.push_shared(mesh);
+        }
+    }
+
+    /// Meshes pushed with [`World::push_light`], collected into a world of their own.
+    pub fn lights(&self) -> World {
+        let mut lights = World::new();
+        for light in &self.lights {
+            lights.push_shared(light.clone());
+        }
+        lights
+    }
+
+    pub fn len(&self) -> usize {
+        self.meshes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
     }
 }
 
@@ -110,7 +220,42 @@ pub struct Sphere<M: Material> {
 }
 
 impl<M: Material> Mesh for Sphere<M> {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, _rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        self.intersect(ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        if self
+            .intersect(ray, &(0.001..f32::INFINITY).into())
+            .is_none()
+        {
+            return 0.;
+        }
+
+        let distance_squared = (self.center(ray.time) - ray.origin).length_squared();
+        let cos_theta_max = (1. - self.radius * self.radius / distance_squared)
+            .max(0.)
+            .sqrt();
+        let solid_angle = 2. * PI * (1. - cos_theta_max);
+
+        1. / solid_angle
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        let direction = self.center(time) - origin;
+        let distance_squared = direction.length_squared();
+        let local = random_to_sphere(rng, self.radius, distance_squared);
+
+        from_local(direction.normalize(), local)
+    }
+}
+
+impl<M: Material> Sphere<M> {
+    fn intersect(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit<'_>> {
         let center = self.center(ray.time);
         let oc = center - ray.origin;
         let a = ray.direction.length_squared();
@@ -138,12 +283,6 @@ impl<M: Material> Mesh for Sphere<M> {
         Some(Hit::new(ray, root, normal, &self.material, uv))
     }
 
-    fn bounding_box(&self) -> &Aabb {
-        &self.bbox
-    }
-}
-
-impl<M: Material> Sphere<M> {
     pub fn stationary(center: Vec3, radius: f32, material: M) -> Self {
         assert!(radius >= 0., "Radius cannot be less than 0.");
 
@@ -158,6 +297,9 @@ impl<M: Material> Sphere<M> {
         }
     }
 
+    /// Sphere moving from `from` at time 0 to `to` at time 1, resting at
+    /// either end outside that interval. Across animation frames, objects are
+    /// moved by keyframes instead.
     pub fn moving(from: Vec3, to: Vec3, radius: f32, material: M) -> Self {
         assert!(radius >= 0., "Radius cannot be less than 0.");
 
@@ -175,8 +317,9 @@ impl<M: Material> Sphere<M> {
         }
     }
 
+    /// Center at `time`, which stays within the bounding box at any time.
     pub fn center(&self, time: f32) -> Vec3 {
-        self.initial_center + self.center_delta * time
+        self.initial_center + self.center_delta * time.clamp(0., 1.)
     }
 
     fn uv(&self, point: Vec3) -> Vec2 {
@@ -187,6 +330,7 @@ impl<M: Material> Sphere<M> {
     }
 }
 
+#[derive(Clone)]
 pub struct Quad<M: Material> {
     translation: Vec3,
     u: Vec3,
@@ -196,10 +340,37 @@ pub struct Quad<M: Material> {
     normal: Vec3,
     d: f32,
     w: Vec3,
+    area: f32,
 }
 
 impl<M: Material> Mesh for Quad<M> {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, _rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        self.intersect(ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        let Some(hit) = self.intersect(ray, &(0.001..f32::INFINITY).into()) else {
+            return 0.;
+        };
+
+        let distance_squared = hit.distance * hit.distance * ray.direction.length_squared();
+        let cosine = (ray.direction.dot(hit.normal) / ray.direction.length()).abs();
+
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: Vec3, _time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        let point = self.translation + rng.gen::<f32>() * self.u + rng.gen::<f32>() * self.v;
+        point - origin
+    }
+}
+
+impl<M: Material> Quad<M> {
+    fn intersect(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit<'_>> {
         let denom = self.normal.dot(ray.direction);
 
         if denom.abs() < 1e-8 {
@@ -220,12 +391,6 @@ impl<M: Material> Mesh for Quad<M> {
         Some(Hit::new(ray, t, self.normal, &self.material, uv))
     }
 
-    fn bounding_box(&self) -> &Aabb {
-        &self.bbox
-    }
-}
-
-impl<M: Material> Quad<M> {
     pub fn new(translation: Vec3, u: Vec3, v: Vec3, material: M) -> Self {
         let bbox_diagonal1 = Aabb::from_extremes(translation, translation + u + v);
         let bbox_diagonal2 = Aabb::from_extremes(translation + u, translation + v);
@@ -242,6 +407,7 @@ impl<M: Material> Quad<M> {
             normal,
             d: normal.dot(translation),
             w: n / n.dot(n),
+            area: n.length(),
         }
     }
 
@@ -259,8 +425,8 @@ impl<M: Material> Quad<M> {
 pub struct Cube(World);
 
 impl Mesh for Cube {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
-        self.0.hit(ray, ray_t)
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        self.0.hit(ray, ray_t, rng)
     }
 
     fn bounding_box(&self) -> &Aabb {
@@ -321,6 +487,301 @@ impl Cube {
     }
 }
 
+#[derive(Clone)]
+pub struct Triangle<M: Material> {
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[Vec2; 3]>,
+    material: M,
+    bbox: Aabb,
+    area: f32,
+}
+
+impl<M: Material> Mesh for Triangle<M> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, _rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        hit_triangle(
+            ray,
+            ray_t,
+            self.vertices,
+            self.normals,
+            self.uvs,
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        triangle_pdf(ray, self.vertices, 1. / self.area)
+    }
+
+    fn random(&self, origin: Vec3, _time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        random_point_in_triangle(rng, self.vertices) - origin
+    }
+}
+
+impl<M: Material> Triangle<M> {
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, material: M) -> Self {
+        Self {
+            vertices: [a, b, c],
+            normals: None,
+            uvs: None,
+            material,
+            bbox: Aabb::from_points(&[a, b, c]),
+            area: (b - a).cross(c - a).length() / 2.,
+        }
+    }
+
+    /// Interpolates the given per-vertex normals instead of using the face normal.
+    pub fn with_normals(self, normals: [Vec3; 3]) -> Self {
+        Self {
+            normals: Some(normals),
+            ..self
+        }
+    }
+
+    /// Interpolates the given per-vertex texture coordinates instead of using
+    /// the barycentric coordinates of the hit.
+    pub fn with_uvs(self, uvs: [Vec2; 3]) -> Self {
+        Self {
+            uvs: Some(uvs),
+            ..self
+        }
+    }
+}
+
+/// Vertex buffers shared by every triangle of a [`TriangleMesh`]. `normals`
+/// and `uvs` are either empty or hold one entry per position.
+#[derive(Default, Clone)]
+pub struct TriangleMeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<Vec2>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+pub struct TriangleMesh {
+    data: Arc<TriangleMeshData>,
+    bvh: Bvh,
+    /// Running sum of triangle areas, used to pick triangles when sampled as a light.
+    area_cdf: Vec<f32>,
+}
+
+impl Mesh for TriangleMesh {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        self.bvh.hit(ray, ray_t, rng)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.bvh.bounding_box()
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.bvh.pdf_sum(ray)
+    }
+
+    fn random(&self, origin: Vec3, _time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        let target = rng.gen::<f32>() * self.area_cdf[self.area_cdf.len() - 1];
+        let index = self
+            .area_cdf
+            .partition_point(|&area| area < target)
+            .min(self.area_cdf.len() - 1);
+
+        random_point_in_triangle(rng, self.data.triangle(index).0) - origin
+    }
+}
+
+impl TriangleMesh {
+    pub fn new(
+        data: impl Into<Arc<TriangleMeshData>>,
+        material: impl Material + Sync + Send + 'static,
+    ) -> Self {
+        let data = data.into();
+        let vertex_count = data.positions.len();
+
+        assert!(
+            !data.indices.is_empty(),
+            "Mesh must have at least one triangle."
+        );
+        assert!(
+            data.normals.is_empty() || data.normals.len() == vertex_count,
+            "Mesh must have no normals or one per vertex."
+        );
+        assert!(
+            data.uvs.is_empty() || data.uvs.len() == vertex_count,
+            "Mesh must have no uvs or one per vertex."
+        );
+        assert!(
+            data.indices
+                .iter()
+                .flatten()
+                .all(|&i| (i as usize) < vertex_count),
+            "Mesh indices must point at existing vertices."
+        );
+
+        let area_cdf: Vec<f32> = (0..data.indices.len())
+            .scan(0., |total, index| {
+                let [a, b, c] = data.triangle(index).0;
+                *total += (b - a).cross(c - a).length() / 2.;
+                Some(*total)
+            })
+            .collect();
+        let area_pdf = 1. / area_cdf[area_cdf.len() - 1];
+
+        let material = Arc::new(material);
+        let triangles: Vec<Arc<dyn Mesh + Sync + Send>> = (0..data.indices.len())
+            .map(|index| {
+                let triangle: Arc<dyn Mesh + Sync + Send> = Arc::new(MeshTriangle {
+                    bbox: Aabb::from_points(&data.triangle(index).0),
+                    data: data.clone(),
+                    material: material.clone(),
+                    index,
+                    area_pdf,
+                });
+                triangle
+            })
+            .collect();
+
+        Self {
+            bvh: Bvh::from(triangles),
+            data,
+            area_cdf,
+        }
+    }
+
+    pub fn data(&self) -> &TriangleMeshData {
+        &self.data
+    }
+}
+
+impl TriangleMeshData {
+    fn triangle(&self, index: usize) -> ([Vec3; 3], Option<[Vec3; 3]>, Option<[Vec2; 3]>) {
+        let indices = self.indices[index].map(|i| i as usize);
+
+        (
+            indices.map(|i| self.positions[i]),
+            (!self.normals.is_empty()).then(|| indices.map(|i| self.normals[i])),
+            (!self.uvs.is_empty()).then(|| indices.map(|i| self.uvs[i])),
+        )
+    }
+}
+
+struct MeshTriangle<M: Material> {
+    data: Arc<TriangleMeshData>,
+    material: Arc<M>,
+    index: usize,
+    bbox: Aabb,
+    /// Area density of sampling a point on the whole mesh as a light.
+    area_pdf: f32,
+}
+
+impl<M: Material> Mesh for MeshTriangle<M> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, _rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        let (vertices, normals, uvs) = self.data.triangle(self.index);
+        hit_triangle(ray, ray_t, vertices, normals, uvs, self.material.as_ref())
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    /// Density of [`TriangleMesh::random`] sampling the point the ray
+    /// pierces this triangle at.
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        triangle_pdf(ray, self.data.triangle(self.index).0, self.area_pdf)
+    }
+}
+
+/// Möller–Trumbore intersection returning the distance along the ray and the
+/// barycentric weights of the second and third vertex.
+fn intersect_triangle(
+    ray: &Ray,
+    ray_t: &Interval,
+    [a, b, c]: [Vec3; 3],
+) -> Option<(f32, f32, f32)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+
+    let pvec = ray.direction.cross(edge2);
+    let det = edge1.dot(pvec);
+    if det.abs() < 1e-8 {
+        return None;
+    }
+
+    let inv_det = 1. / det;
+    let tvec = ray.origin - a;
+    let u = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(edge1);
+    let v = ray.direction.dot(qvec) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let t = edge2.dot(qvec) * inv_det;
+    if !ray_t.contains(t) {
+        return None;
+    }
+
+    Some((t, u, v))
+}
+
+/// Solid angle density of sampling the point where `ray` pierces the triangle
+/// with `area_pdf`, or 0 if the ray misses it.
+fn triangle_pdf(ray: &Ray, vertices: [Vec3; 3], area_pdf: f32) -> f32 {
+    let Some((distance, _, _)) = intersect_triangle(ray, &(0.001..f32::INFINITY).into(), vertices)
+    else {
+        return 0.;
+    };
+
+    let [a, b, c] = vertices;
+    let normal = (b - a).cross(c - a).normalize();
+    let distance_squared = distance * distance * ray.direction.length_squared();
+    let cosine = (ray.direction.dot(normal) / ray.direction.length()).abs();
+
+    distance_squared * area_pdf / cosine
+}
+
+fn random_point_in_triangle(mut rng: impl Rng, [a, b, c]: [Vec3; 3]) -> Vec3 {
+    let (mut u, mut v) = (rng.gen::<f32>(), rng.gen::<f32>());
+    if u + v > 1. {
+        (u, v) = (1. - u, 1. - v);
+    }
+
+    a + u * (b - a) + v * (c - a)
+}
+
+fn hit_triangle<'a>(
+    ray: &Ray,
+    ray_t: &Interval,
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[Vec2; 3]>,
+    material: &'a dyn Material,
+) -> Option<Hit<'a>> {
+    let (t, u, v) = intersect_triangle(ray, ray_t, vertices)?;
+    let w = 1. - u - v;
+
+    let normal = match normals {
+        Some([na, nb, nc]) => (w * na + u * nb + v * nc).normalize(),
+        None => {
+            let [a, b, c] = vertices;
+            (b - a).cross(c - a).normalize()
+        }
+    };
+    let uv = match uvs {
+        Some([ta, tb, tc]) => w * ta + u * tb + v * tc,
+        None => Vec2::new(u, v),
+    };
+
+    Some(Hit::new(ray, t, normal, material, uv))
+}
+
 pub struct Translate<T: Mesh> {
     bbox: Aabb,
     offset: Vec3,
@@ -328,9 +789,9 @@ pub struct Translate<T: Mesh> {
 }
 
 impl<T: Mesh> Mesh for Translate<T> {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
         let ray = Ray::new(ray.origin - self.offset, ray.direction, ray.time);
-        if let Some(mut hit) = self.object.hit(&ray, ray_t) {
+        if let Some(mut hit) = self.object.hit(&ray, ray_t, rng) {
             hit.point += self.offset;
             Some(hit)
         } else {
@@ -341,6 +802,15 @@ impl<T: Mesh> Mesh for Translate<T> {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.object
+            .pdf_value(&Ray::new(ray.origin - self.offset, ray.direction, ray.time))
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(origin - self.offset, time, rng)
+    }
 }
 
 impl<T: Mesh> Translate<T> {
@@ -361,7 +831,7 @@ pub struct RotateY<T: Mesh> {
 }
 
 impl<T: Mesh> Mesh for RotateY<T> {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
         let mut origin = ray.origin;
         let mut direction = ray.direction;
 
@@ -373,7 +843,7 @@ impl<T: Mesh> Mesh for RotateY<T> {
 
         if let Some(mut hit) = self
             .object
-            .hit(&Ray::new(origin, direction, ray.time), ray_t)
+            .hit(&Ray::new(origin, direction, ray.time), ray_t, rng)
         {
             hit.point[0] = self.cos_theta * hit.point[0] - self.sin_theta * hit.point[2];
             hit.point[2] = self.sin_theta * hit.point[0] + self.cos_theta * hit.point[2];
@@ -431,6 +901,304 @@ impl<T: Mesh> RotateY<T> {
     }
 }
 
+/// Applies an arbitrary affine transform to the wrapped mesh.
+pub struct Transform<T: Mesh> {
+    object: T,
+    map: AffineMap,
+    bbox: Aabb,
+}
+
+impl<T: Mesh> Mesh for Transform<T> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        self.map.hit(&self.object, ray, ray_t, rng)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.map.pdf_value(&self.object, ray)
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        self.map.random(&self.object, origin, time, rng)
+    }
+}
+
+impl<T: Mesh> Transform<T> {
+    pub fn new(object: T, matrix: Affine3A) -> Self {
+        Self {
+            bbox: object.bounding_box().transform(&matrix),
+            object,
+            map: AffineMap::new(matrix),
+        }
+    }
+}
+
+/// Pose of an animated object at a point in time.
+#[derive(Clone, Copy)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f32) -> Self {
+        Self {
+            time,
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+        }
+    }
+
+    pub fn with_translation(self, translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..self
+        }
+    }
+
+    pub fn with_rotation(self, rotation: Quat) -> Self {
+        Self { rotation, ..self }
+    }
+
+    pub fn with_scale(self, scale: Vec3) -> Self {
+        Self { scale, ..self }
+    }
+
+    /// Scales, then rotates, then translates.
+    pub fn matrix(&self) -> Affine3A {
+        Affine3A::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    fn interpolate(&self, next: &Self, time: f32) -> Self {
+        let t = ((time - self.time) / (next.time - self.time)).clamp(0., 1.);
+
+        Self {
+            time,
+            translation: self.translation.lerp(next.translation, t),
+            rotation: self.rotation.slerp(next.rotation, t),
+            scale: self.scale.lerp(next.scale, t),
+        }
+    }
+}
+
+/// Samples taken between two keyframes when bounding the motion.
+const MOTION_BOUND_STEPS: usize = 32;
+
+/// Moves the wrapped mesh through keyframes interpolated by [`Ray::time`].
+/// Before the first and after the last keyframe the object stays still.
+pub struct AnimatedTransform<T: Mesh> {
+    object: T,
+    keyframes: Vec<Keyframe>,
+    bbox: Aabb,
+}
+
+impl<T: Mesh> Mesh for AnimatedTransform<T> {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        self.map(ray.time).hit(&self.object, ray, ray_t, rng)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.map(ray.time).pdf_value(&self.object, ray)
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        self.map(time).random(&self.object, origin, time, rng)
+    }
+}
+
+impl<T: Mesh> AnimatedTransform<T> {
+    pub fn new(object: T, mut keyframes: Vec<Keyframe>) -> Self {
+        assert!(
+            !keyframes.is_empty(),
+            "animation needs at least one keyframe"
+        );
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self {
+            bbox: Self::motion_bounds(object.bounding_box(), &keyframes),
+            object,
+            keyframes,
+        }
+    }
+
+    fn keyframe(&self, time: f32) -> Keyframe {
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        match next {
+            0 => self.keyframes[0],
+            next if next == self.keyframes.len() => self.keyframes[next - 1],
+            next => self.keyframes[next - 1].interpolate(&self.keyframes[next], time),
+        }
+    }
+
+    fn map(&self, time: f32) -> AffineMap {
+        AffineMap::new(self.keyframe(time).matrix())
+    }
+
+    /// Bounds `bbox` over the whole motion by sampling every segment and
+    /// padding for the arcs rotating corners trace between samples.
+    fn motion_bounds(bbox: &Aabb, keyframes: &[Keyframe]) -> Aabb {
+        let mut bounds = bbox.transform(&keyframes[0].matrix());
+        let mut max_step_angle: f32 = 0.;
+
+        for pair in keyframes.windows(2) {
+            let [from, to] = pair else { unreachable!() };
+            for step in 1..=MOTION_BOUND_STEPS {
+                let time =
+                    from.time + (to.time - from.time) * step as f32 / MOTION_BOUND_STEPS as f32;
+                let keyframe = from.interpolate(to, time);
+                bounds = bounds.merge(&bbox.transform(&keyframe.matrix()));
+            }
+
+            let angle = from.rotation.angle_between(to.rotation);
+            max_step_angle = max_step_angle.max(angle / MOTION_BOUND_STEPS as f32);
+        }
+
+        if max_step_angle <= 0. {
+            return bounds;
+        }
+
+        let corner_distance = (0..8)
+            .map(|corner| bbox.corner(corner).length())
+            .fold(0., f32::max);
+        let max_scale = keyframes
+            .iter()
+            .map(|keyframe| keyframe.scale.abs().max_element())
+            .fold(0., f32::max);
+        let sagitta = corner_distance * max_scale * (1. - (max_step_angle / 2.).cos());
+
+        Aabb::new(
+            bounds.x.expand(2. * sagitta),
+            bounds.y.expand(2. * sagitta),
+            bounds.z.expand(2. * sagitta),
+        )
+    }
+}
+
+/// Affine map between world and object space, with everything needed to
+/// carry rays there and hits back.
+struct AffineMap {
+    matrix: Affine3A,
+    inverse: Affine3A,
+    /// Inverse transpose of the linear part, which keeps normals perpendicular to surfaces.
+    normal_matrix: Mat3A,
+}
+
+impl AffineMap {
+    fn new(matrix: Affine3A) -> Self {
+        let inverse = matrix.inverse();
+
+        Self {
+            matrix,
+            inverse,
+            normal_matrix: inverse.matrix3.transpose(),
+        }
+    }
+
+    fn to_object(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point3(ray.origin),
+            self.inverse.transform_vector3(ray.direction),
+            ray.time,
+        )
+    }
+
+    fn hit<'a>(
+        &self,
+        object: &'a impl Mesh,
+        ray: &Ray,
+        ray_t: &Interval,
+        rng: &mut dyn RngCore,
+    ) -> Option<Hit<'a>> {
+        // The object space direction is left unnormalized so that hit
+        // distances stay valid in world space.
+        let mut hit = object.hit(&self.to_object(ray), ray_t, rng)?;
+        hit.point = self.matrix.transform_point3(hit.point);
+        hit.normal = (self.normal_matrix * Vec3A::from(hit.normal))
+            .normalize()
+            .into();
+        Some(hit)
+    }
+
+    fn pdf_value(&self, object: &impl Mesh, ray: &Ray) -> f32 {
+        let local = self.to_object(ray);
+        let pdf = object.pdf_value(&local);
+        if pdf <= 0. {
+            return 0.;
+        }
+
+        // Solid angles are stretched by the transform, with Jacobian
+        // |det M| / |M w|^3 for the map M taking unit world directions w to
+        // object space.
+        let stretch = local.direction.length() / ray.direction.length();
+        pdf * self.inverse.matrix3.determinant().abs() / stretch.powi(3)
+    }
+
+    fn random(&self, object: &impl Mesh, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        let direction = object.random(self.inverse.transform_point3(origin), time, rng);
+        self.matrix.transform_vector3(direction)
+    }
+}
+
+/// One placement of a prototype mesh that may be shared by any number of
+/// instances, optionally replacing the prototype's materials.
+///
+/// Instances are meshes themselves, so a [`Bvh`] built over them serves as
+/// the top-level acceleration structure while the prototype keeps its own.
+pub struct Instance {
+    transform: Transform<Arc<dyn Mesh + Sync + Send>>,
+    material: Option<Arc<dyn Material + Sync + Send>>,
+}
+
+impl Mesh for Instance {
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        let mut hit = self.transform.hit(ray, ray_t, rng)?;
+        if let Some(material) = &self.material {
+            hit.material = material.as_ref();
+        }
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.transform.bounding_box()
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        self.transform.pdf_value(ray)
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        self.transform.random(origin, time, rng)
+    }
+}
+
+impl Instance {
+    pub fn new(prototype: Arc<dyn Mesh + Sync + Send>, matrix: Affine3A) -> Self {
+        Self {
+            transform: Transform::new(prototype, matrix),
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: impl Material + Sync + Send + 'static) -> Self {
+        Self {
+            material: Some(Arc::new(material)),
+            ..self
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct Aabb {
     x: Interval,
@@ -464,6 +1232,13 @@ impl Add<Vec3> for &Aabb {
 }
 
 impl Aabb {
+    /// Box containing nothing, the identity of [`Aabb::merge`].
+    pub const EMPTY: Self = Self {
+        x: Interval::EMPTY,
+        y: Interval::EMPTY,
+        z: Interval::EMPTY,
+    };
+
     pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
         Self {
             x: Self::pad_to_minimus(x),
@@ -481,6 +1256,14 @@ impl Aabb {
         }
     }
 
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let (min, max) = points.iter().fold(
+            (Vec3::INFINITY, Vec3::NEG_INFINITY),
+            |(min, max), &point| (min.min(point), max.max(point)),
+        );
+        Self::from_extremes(min, max)
+    }
+
     pub fn from_extremes(a: Vec3, b: Vec3) -> Self {
         Self::new(
             if a.x <= b.x {
@@ -510,15 +1293,23 @@ impl Aabb {
     }
 
     pub fn hit(&self, ray: &Ray, ray_t: &Interval) -> bool {
-        let mut ray_t = ray_t.clone();
+        self.hit_inverse(
+            ray.origin,
+            ray.direction.recip(),
+            ray_t.start(),
+            ray_t.end(),
+        )
+    }
 
+    /// Slab test against a ray whose reciprocal direction is already known.
+    fn hit_inverse(&self, origin: Vec3, inv_direction: Vec3, mut tmin: f32, mut tmax: f32) -> bool {
         for n in 0..3 {
             let interval = &self[n];
-            let adinv = 1.0 / ray.direction[n];
+            let adinv = inv_direction[n];
 
             let (t0, t1) = {
-                let t0 = (interval.start() - ray.origin[n]) * adinv;
-                let t1 = (interval.end() - ray.origin[n]) * adinv;
+                let t0 = (interval.start() - origin[n]) * adinv;
+                let t1 = (interval.end() - origin[n]) * adinv;
                 if t0 < t1 {
                     (t0, t1)
                 } else {
@@ -526,10 +1317,10 @@ impl Aabb {
                 }
             };
 
-            ray_t.set_start(ray_t.start().max(t0));
-            ray_t.set_end(ray_t.end().min(t1));
+            tmin = tmin.max(t0);
+            tmax = tmax.min(t1);
 
-            if ray_t.end() <= ray_t.start() {
+            if tmax <= tmin {
                 return false;
             }
         }
@@ -537,6 +1328,40 @@ impl Aabb {
         true
     }
 
+    /// One of the eight corners, picking the upper bound on axis `n` when bit `n` of `index` is set.
+    pub fn corner(&self, index: usize) -> Vec3 {
+        let pick = |axis: usize| {
+            if index & (1 << axis) == 0 {
+                self[axis].start()
+            } else {
+                self[axis].end()
+            }
+        };
+        Vec3::new(pick(0), pick(1), pick(2))
+    }
+
+    /// Smallest box containing this one after applying `matrix`.
+    pub fn transform(&self, matrix: &Affine3A) -> Self {
+        let corners: Vec<Vec3> = (0..8)
+            .map(|corner| matrix.transform_point3(self.corner(corner)))
+            .collect();
+
+        Self::from_points(&corners)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        Vec3::new(
+            (self.x.start() + self.x.end()) / 2.,
+            (self.y.start() + self.y.end()) / 2.,
+            (self.z.start() + self.z.end()) / 2.,
+        )
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2. * (x * y + y * z + z * x)
+    }
+
     pub fn longest_axis(&self) -> usize {
         let x = self.x.end() - self.x.start();
         let y = self.y.end() - self.y.start();
@@ -551,10 +1376,43 @@ impl Aabb {
     }
 }
 
+/// How [`Bvh`] partitions meshes between its children.
+#[derive(Clone, Copy, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BvhStrategy {
+    /// Sorts on the longest axis and splits at the median.
+    Median,
+    /// Picks the cheapest binned split according to the surface area heuristic.
+    #[default]
+    Sah,
+}
+
+/// Meshes are kept together in a leaf once a node holds at most this many.
+const BVH_MAX_LEAF_SIZE: usize = 4;
+/// Number of candidate split planes evaluated per node by the SAH builder.
+const BVH_SAH_BINS: usize = 12;
+/// Cost of visiting a node, relative to intersecting a single mesh.
+const BVH_TRAVERSAL_COST: f32 = 1.;
+/// Deepest level a node may be built at, bounding the traversal stack.
+const BVH_MAX_DEPTH: usize = 64;
+
+/// Bounding volume hierarchy stored as a depth-first array of nodes.
 pub struct Bvh {
-    left: Arc<dyn Mesh + Sync + Send>,
-    right: Arc<dyn Mesh + Sync + Send>,
+    nodes: Vec<BvhNode>,
+    /// Meshes ordered so that every leaf references a contiguous range.
+    meshes: Vec<Arc<dyn Mesh + Sync + Send>>,
+}
+
+struct BvhNode {
     bbox: Aabb,
+    kind: BvhNodeKind,
+}
+
+enum BvhNodeKind {
+    /// Holds `meshes[first..first + count]`.
+    Leaf { first: usize, count: usize },
+    /// The first child directly follows its parent in the node array.
+    Interior { second: usize, axis: usize },
 }
 
 impl<T> From<T> for Bvh
@@ -562,65 +1420,284 @@ where
     T: AsRef<[Arc<dyn Mesh + Sync + Send>]>,
 {
     fn from(value: T) -> Self {
-        let value = value.as_ref();
+        Self::new(value, BvhStrategy::default())
+    }
+}
 
-        let bbox = value
+impl Bvh {
+    pub fn new(meshes: impl AsRef<[Arc<dyn Mesh + Sync + Send>]>, strategy: BvhStrategy) -> Self {
+        let meshes = meshes.as_ref();
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(meshes.len() * 2),
+            meshes: Vec::with_capacity(meshes.len()),
+        };
+        bvh.build(meshes.to_vec(), strategy, 0);
+        bvh
+    }
+
+    /// Appends the subtree over `meshes` in depth-first order and returns the index of its root.
+    fn build(
+        &mut self,
+        mut meshes: Vec<Arc<dyn Mesh + Sync + Send>>,
+        strategy: BvhStrategy,
+        depth: usize,
+    ) -> usize {
+        let bbox = meshes
             .iter()
-            .fold(Aabb::default(), |acc, mesh| acc.merge(&mesh.bounding_box()));
-        let longest_axis = bbox.longest_axis();
+            .fold(Aabb::EMPTY, |acc, mesh| acc.merge(mesh.bounding_box()));
 
-        if value.len() == 1 {
-            Self {
-                left: value[0].clone(),
-                right: value[0].clone(),
-                bbox,
-            }
-        } else if value.len() == 2 {
-            Self {
-                left: value[0].clone(),
-                right: value[1].clone(),
-                bbox,
+        let index = self.nodes.len();
+        let split = if depth + 1 < BVH_MAX_DEPTH {
+            match strategy {
+                BvhStrategy::Median => Self::split_median(&mut meshes, &bbox),
+                BvhStrategy::Sah => Self::split_sah(&mut meshes, &bbox),
             }
         } else {
-            let mut value = Vec::from(value);
+            None
+        };
 
-            value.sort_by(|a, b| {
-                a.bounding_box()[longest_axis]
-                    .start()
-                    .total_cmp(&b.bounding_box()[longest_axis].start())
+        let Some((right, axis)) = split else {
+            self.nodes.push(BvhNode {
+                bbox,
+                kind: BvhNodeKind::Leaf {
+                    first: self.meshes.len(),
+                    count: meshes.len(),
+                },
             });
+            self.meshes.extend(meshes);
+            return index;
+        };
 
-            let mid = value.len() / 2;
+        self.nodes.push(BvhNode {
+            bbox,
+            kind: BvhNodeKind::Leaf { first: 0, count: 0 },
+        });
+        self.build(meshes, strategy, depth + 1);
+        let second = self.build(right, strategy, depth + 1);
+        self.nodes[index].kind = BvhNodeKind::Interior { second, axis };
 
-            let left = Bvh::from(&value[..mid]);
-            let right = Bvh::from(&value[mid..]);
+        index
+    }
 
-            Self {
-                bbox,
-                left: Arc::new(left),
-                right: Arc::new(right),
+    /// Moves the upper half of `meshes`, sorted on the longest axis of `bbox`,
+    /// into a new vector returned along with that axis.
+    fn split_median(
+        meshes: &mut Vec<Arc<dyn Mesh + Sync + Send>>,
+        bbox: &Aabb,
+    ) -> Option<(Vec<Arc<dyn Mesh + Sync + Send>>, usize)> {
+        if meshes.len() <= BVH_MAX_LEAF_SIZE {
+            return None;
+        }
+
+        let longest_axis = bbox.longest_axis();
+        meshes.sort_by(|a, b| {
+            a.bounding_box()[longest_axis]
+                .start()
+                .total_cmp(&b.bounding_box()[longest_axis].start())
+        });
+
+        Some((meshes.split_off(meshes.len() / 2), longest_axis))
+    }
+
+    /// Moves the meshes above the cheapest split plane into a new vector
+    /// returned along with the plane's axis, or returns `None` when keeping
+    /// them in a leaf is cheaper.
+    fn split_sah(
+        meshes: &mut Vec<Arc<dyn Mesh + Sync + Send>>,
+        bbox: &Aabb,
+    ) -> Option<(Vec<Arc<dyn Mesh + Sync + Send>>, usize)> {
+        if meshes.len() <= 1 {
+            return None;
+        }
+
+        let (min, max) =
+            meshes
+                .iter()
+                .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), mesh| {
+                    let centroid = mesh.bounding_box().centroid();
+                    (min.min(centroid), max.max(centroid))
+                });
+        let extent = max - min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+
+        // All centroids coincide, so no plane separates them.
+        if extent[axis] <= 0. {
+            return Self::split_median(meshes, bbox);
+        }
+
+        let bin_of = |mesh: &Arc<dyn Mesh + Sync + Send>| {
+            let offset = (mesh.bounding_box().centroid()[axis] - min[axis]) / extent[axis];
+            ((offset * BVH_SAH_BINS as f32) as usize).min(BVH_SAH_BINS - 1)
+        };
+
+        let mut bins: [(Aabb, usize); BVH_SAH_BINS] = array::from_fn(|_| (Aabb::EMPTY, 0));
+        for mesh in meshes.iter() {
+            let bin = &mut bins[bin_of(mesh)];
+            bin.0 = bin.0.merge(mesh.bounding_box());
+            bin.1 += 1;
+        }
+
+        // Area-weighted mesh counts of everything at or above each bin.
+        let mut right_costs = [0.; BVH_SAH_BINS];
+        let mut right = (Aabb::EMPTY, 0);
+        for (cost, bin) in right_costs.iter_mut().zip(&bins).rev() {
+            right = (right.0.merge(&bin.0), right.1 + bin.1);
+            *cost = right.0.surface_area() * right.1 as f32;
+        }
+
+        // The first and last bins always hold a centroid, so both sides of
+        // every candidate plane are non-empty.
+        let mut left = (Aabb::EMPTY, 0);
+        let (split, cost) = (1..BVH_SAH_BINS)
+            .map(|split| {
+                let bin = &bins[split - 1];
+                left = (left.0.merge(&bin.0), left.1 + bin.1);
+                let cost = BVH_TRAVERSAL_COST
+                    + (left.0.surface_area() * left.1 as f32 + right_costs[split])
+                        / bbox.surface_area();
+                (split, cost)
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        if meshes.len() <= BVH_MAX_LEAF_SIZE && meshes.len() as f32 <= cost {
+            return None;
+        }
+
+        let (below, above) = meshes.drain(..).partition(|mesh| bin_of(mesh) < split);
+        *meshes = below;
+        Some((above, axis))
+    }
+
+    /// Sum of [`Mesh::pdf_value`] over the meshes whose boxes `ray` crosses,
+    /// which are the only ones that can lie along it.
+    fn pdf_sum(&self, ray: &Ray) -> f32 {
+        let inv_direction = ray.direction.recip();
+        let mut sum = 0.;
+
+        let mut stack = [0; BVH_MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            if node
+                .bbox
+                .hit_inverse(ray.origin, inv_direction, 0.001, f32::INFINITY)
+            {
+                match node.kind {
+                    BvhNodeKind::Leaf { first, count } => {
+                        sum += self.meshes[first..first + count]
+                            .iter()
+                            .map(|mesh| mesh.pdf_value(ray))
+                            .sum::<f32>();
+                    }
+                    BvhNodeKind::Interior { second, .. } => {
+                        stack[stack_len] = second;
+                        stack_len += 1;
+                        index += 1;
+                        continue;
+                    }
+                }
+            }
+
+            if stack_len == 0 {
+                break;
             }
+            stack_len -= 1;
+            index = stack[stack_len];
         }
+
+        sum
     }
 }
 
 impl Mesh for Bvh {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
-        if self.bbox.hit(ray, ray_t) {
-            match self.left.hit(ray, ray_t) {
-                Some(hit) => self
-                    .right
-                    .hit(ray, &(ray_t.start()..hit.distance).into())
-                    .or(Some(hit)),
-                None => self.right.hit(ray, ray_t),
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        let inv_direction = ray.direction.recip();
+        let mut current_hit: Option<Hit> = None;
+
+        let mut stack = [0; BVH_MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            let tmax = current_hit
+                .as_ref()
+                .map(|hit| hit.distance)
+                .unwrap_or(ray_t.end());
+
+            if node
+                .bbox
+                .hit_inverse(ray.origin, inv_direction, ray_t.start(), tmax)
+            {
+                match node.kind {
+                    BvhNodeKind::Leaf { first, count } => {
+                        for mesh in &self.meshes[first..first + count] {
+                            let tmax = current_hit
+                                .as_ref()
+                                .map(|hit| hit.distance)
+                                .unwrap_or(ray_t.end());
+                            let t = (ray_t.start()..tmax).into();
+
+                            if let Some(hit) = mesh.hit(ray, &t, rng) {
+                                current_hit = Some(Hit {
+                                    object_id: object_id(mesh),
+                                    ..hit
+                                });
+                            }
+                        }
+                    }
+                    BvhNodeKind::Interior { second, axis } => {
+                        // Visit the child nearer to the ray origin first so
+                        // that its hits cull the farther one.
+                        let (near, far) = if ray.direction[axis] < 0. {
+                            (second, index + 1)
+                        } else {
+                            (index + 1, second)
+                        };
+                        stack[stack_len] = far;
+                        stack_len += 1;
+                        index = near;
+                        continue;
+                    }
+                }
             }
-        } else {
-            None
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len];
         }
+
+        current_hit
     }
 
     fn bounding_box(&self) -> &Aabb {
-        &self.bbox
+        &self.nodes[0].bbox
+    }
+
+    /// Average density of the meshes, as [`Mesh::random`] picks one of them
+    /// uniformly.
+    fn pdf_value(&self, ray: &Ray) -> f32 {
+        if self.meshes.is_empty() {
+            return 0.;
+        }
+
+        self.pdf_sum(ray) / self.meshes.len() as f32
+    }
+
+    fn random(&self, origin: Vec3, time: f32, rng: &mut dyn RngCore) -> Vec3 {
+        self.meshes
+            .choose(rng)
+            .map_or(Vec3::X, |mesh| mesh.random(origin, time, rng))
     }
 }
 
@@ -631,11 +1708,11 @@ pub struct ConstantMedium<B: Mesh, T: Texture> {
 }
 
 impl<B: Mesh, T: Texture> Mesh for ConstantMedium<B, T> {
-    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
-        let mut hit1 = self.boundary.hit(ray, &Interval::UNIVERSE)?;
-        let mut hit2 = self
-            .boundary
-            .hit(ray, &(hit1.distance + 0.0001..f32::INFINITY).into())?;
+    fn hit(&self, ray: &Ray, ray_t: &Interval, rng: &mut dyn RngCore) -> Option<Hit<'_>> {
+        let mut hit1 = self.boundary.hit(ray, &Interval::UNIVERSE, rng)?;
+        let mut hit2 =
+            self.boundary
+                .hit(ray, &(hit1.distance + 0.0001..f32::INFINITY).into(), rng)?;
 
         if hit1.distance < ray_t.start() {
             hit1.distance = ray_t.start();
@@ -656,7 +1733,7 @@ impl<B: Mesh, T: Texture> Mesh for ConstantMedium<B, T> {
         let ray_length = ray.direction.length();
         let distance_inside_boundary = (hit2.distance - hit1.distance) * ray_length;
         let density = -1. / self.density;
-        let hit_distance = density * rand::random::<f32>().ln();
+        let hit_distance = density * rng.gen::<f32>().ln();
 
         if hit_distance > distance_inside_boundary {
             return None;
@@ -671,11 +1748,12 @@ impl<B: Mesh, T: Texture> Mesh for ConstantMedium<B, T> {
             front_face: true,
             material: &self.material,
             uv: Vec2::ZERO,
+            object_id: 0,
         })
     }
 
     fn bounding_box(&self) -> &Aabb {
-        &self.boundary.bounding_box()
+        self.boundary.bounding_box()
     }
 }
 
@@ -694,3 +1772,227 @@ impl<B: Mesh, T: Texture> ConstantMedium<B, T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+    use crate::{material::Lambertian, utils::random_unit_vec};
+
+    fn rng() -> SmallRng {
+        SmallRng::seed_from_u64(1)
+    }
+
+    fn gray() -> Lambertian<SolidTexture> {
+        Lambertian::from(Color::srgb(0.5, 0.5, 0.5))
+    }
+
+    fn hit(mesh: &impl Mesh, ray: &Ray) -> Option<(f32, Vec3)> {
+        let hit = mesh.hit(ray, &(0.001..f32::INFINITY).into(), &mut rng())?;
+        Some((hit.distance, hit.normal))
+    }
+
+    /// Quad stretched unevenly along its edges and tilted away from the
+    /// origin, which keeps it from projecting to a simple shape.
+    fn stretched_quad() -> (Transform<Quad<Lambertian<SolidTexture>>>, Affine3A) {
+        let matrix = Affine3A::from_scale_rotation_translation(
+            Vec3::new(2., 0.5, 1.5),
+            Quat::from_euler(bevy_math::EulerRot::XYZ, 0.4, -0.7, 0.2),
+            Vec3::new(0.3, -0.2, -1.2),
+        );
+        let quad = Quad::new(Vec3::new(-0.5, -0.5, 0.), Vec3::X, Vec3::Y, gray());
+        (Transform::new(quad, matrix), matrix)
+    }
+
+    #[test]
+    fn transformed_pdf_integrates_to_one() {
+        let (quad, _) = stretched_quad();
+        let mut rng = rng();
+
+        // Uniform directions over the sphere: the density has unit integral
+        // and is nonzero exactly on the solid angle the quad covers.
+        let count = 400_000;
+        let (mut integral, mut covered) = (0., 0);
+        for _ in 0..count {
+            let ray = Ray::new(Vec3::ZERO, random_unit_vec(&mut rng), 0.);
+            let pdf = quad.pdf_value(&ray);
+            integral += pdf as f64 * 4. * PI as f64;
+            if hit(&quad, &ray).is_some() {
+                assert!(pdf > 0.);
+                covered += 1;
+            }
+        }
+        let integral = integral / count as f64;
+        assert!((integral - 1.).abs() < 0.03, "pdf integrates to {integral}");
+
+        // The directions `random` picks average 1 / pdf to that solid angle.
+        let solid_angle = covered as f64 / count as f64 * 4. * PI as f64;
+        let samples = 100_000;
+        let mean_inverse = (0..samples)
+            .map(|_| {
+                let direction = quad.random(Vec3::ZERO, 0., &mut rng);
+                let pdf = quad.pdf_value(&Ray::new(Vec3::ZERO, direction, 0.));
+                1. / pdf as f64
+            })
+            .sum::<f64>()
+            / samples as f64;
+        assert!(
+            (mean_inverse / solid_angle - 1.).abs() < 0.03,
+            "{mean_inverse} vs {solid_angle}"
+        );
+    }
+
+    #[test]
+    fn transformed_normals_stay_perpendicular() {
+        let (quad, matrix) = stretched_quad();
+        let tangents =
+            [Vec3::X, Vec3::Y, Vec3::new(1., 1., 0.)].map(|t| matrix.transform_vector3(t));
+        let mut rng = rng();
+
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let ray = Ray::new(Vec3::ZERO, random_unit_vec(&mut rng), 0.);
+            let Some((_, normal)) = hit(&quad, &ray) else {
+                continue;
+            };
+            hits += 1;
+            assert!((normal.length() - 1.).abs() < 1e-5);
+            for tangent in tangents {
+                assert!(normal.dot(tangent.normalize()).abs() < 1e-5);
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    type Shared = Arc<dyn Mesh + Sync + Send>;
+
+    fn sphere(center: Vec3, radius: f32) -> Shared {
+        Arc::new(Sphere::stationary(center, radius, gray()))
+    }
+
+    fn triangle(a: Vec3, b: Vec3, c: Vec3) -> Shared {
+        Arc::new(Triangle::new(a, b, c, gray()))
+    }
+
+    fn random_point(rng: &mut SmallRng, extent: f32) -> Vec3 {
+        Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 2. * extent - extent
+    }
+
+    /// Checks that both BVH builders find the same nearest hit as testing
+    /// every mesh in turn, for rays from all around `meshes` and aimed at them.
+    fn assert_bvh_matches_brute_force(meshes: Vec<Shared>) {
+        let mut world = World::new();
+        for mesh in &meshes {
+            world.push_shared(mesh.clone());
+        }
+        let mut rng = rng();
+
+        for strategy in [BvhStrategy::Sah, BvhStrategy::Median] {
+            let bvh = Bvh::new(&world, strategy);
+            let mut hits = 0;
+            for _ in 0..2000 {
+                let origin = random_point(&mut rng, 12.);
+                let direction = match rng.gen::<bool>() {
+                    true => random_unit_vec(&mut rng),
+                    false => {
+                        let target = meshes.choose(&mut rng).unwrap().bounding_box().centroid();
+                        target + random_point(&mut rng, 0.5) - origin
+                    }
+                };
+                let ray = Ray::new(origin, direction, 0.);
+
+                let expected = hit(&world, &ray);
+                assert_eq!(hit(&bvh, &ray), expected, "{origin} towards {direction}");
+                hits += expected.is_some() as usize;
+            }
+            assert!(hits > 200, "only {hits} rays hit anything");
+        }
+    }
+
+    #[test]
+    fn bvh_finds_nearest_hits_among_random_meshes() {
+        let mut rng = rng();
+        let mut meshes = Vec::new();
+        for _ in 0..200 {
+            let center = random_point(&mut rng, 10.);
+            meshes.push(sphere(center, rng.gen_range(0.1..1.5)));
+            let corner = random_point(&mut rng, 10.);
+            meshes.push(triangle(
+                corner,
+                corner + random_point(&mut rng, 2.),
+                corner + random_point(&mut rng, 2.),
+            ));
+        }
+        assert_bvh_matches_brute_force(meshes);
+    }
+
+    #[test]
+    fn bvh_handles_coinciding_centroids() {
+        // Nested spheres and triangles whose boxes all center on the origin,
+        // along with exact duplicates.
+        let mut meshes = Vec::new();
+        for i in 1..=20 {
+            let r = i as f32 * 0.25;
+            meshes.push(sphere(Vec3::ZERO, r));
+            meshes.push(triangle(
+                Vec3::new(r, 0., -r),
+                Vec3::new(-r, r, 0.),
+                Vec3::new(0., -r, r),
+            ));
+        }
+        meshes.extend((0..10).map(|_| sphere(Vec3::ZERO, 0.7)));
+        assert_bvh_matches_brute_force(meshes);
+    }
+
+    #[test]
+    fn bvh_handles_empty_bins() {
+        // Centroids at two far apart spots leave every bin in between empty,
+        // and a lone outlier squeezes a dense cluster into a single bin.
+        let mut rng = rng();
+        let mut meshes = Vec::new();
+        for x in [-8., 8.] {
+            for _ in 0..12 {
+                meshes.push(sphere(Vec3::new(x, 0., 0.), rng.gen_range(0.5..3.)));
+            }
+        }
+        for _ in 0..30 {
+            let corner = Vec3::new(0., 5., 0.) + random_point(&mut rng, 0.01);
+            meshes.push(triangle(corner, corner + Vec3::X, corner + Vec3::Z));
+        }
+        meshes.push(sphere(Vec3::splat(-9.), 0.5));
+        assert_bvh_matches_brute_force(meshes);
+    }
+
+    #[test]
+    fn motion_bounds_hold_the_object_at_all_times() {
+        let quad = Quad::new(Vec3::new(1., 0.5, -2.), Vec3::X * 3., Vec3::Y, gray());
+        let keyframes = vec![
+            Keyframe::new(0.),
+            Keyframe::new(0.3)
+                .with_translation(Vec3::new(2., -1., 0.5))
+                .with_rotation(Quat::from_rotation_y(2.8))
+                .with_scale(Vec3::new(1.5, 0.5, 1.)),
+            Keyframe::new(1.)
+                .with_translation(Vec3::new(-1., 3., 0.))
+                .with_rotation(Quat::from_euler(bevy_math::EulerRot::XYZ, 1., -2., 0.5))
+                .with_scale(Vec3::splat(0.7)),
+        ];
+        let object_bbox = quad.bounding_box().clone();
+        let animated = AnimatedTransform::new(quad, keyframes);
+        let bounds = animated.bounding_box();
+
+        // Times before the first and after the last keyframe included.
+        for step in 0..=2000 {
+            let time = -0.2 + 1.4 * step as f32 / 2000.;
+            let moved = object_bbox.transform(&animated.keyframe(time).matrix());
+            for axis in 0..3 {
+                assert!(
+                    bounds[axis].start() <= moved[axis].start() + 1e-4
+                        && moved[axis].end() <= bounds[axis].end() + 1e-4,
+                    "object leaves the bounds along axis {axis} at time {time}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,625 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+    str::{FromStr, SplitWhitespace},
+    sync::Arc,
+};
+
+use bevy_color::{Color, LinearRgba};
+use bevy_math::{Vec2, Vec3};
+use image::ImageError;
+
+use crate::{
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    mesh::{TriangleMesh, TriangleMeshData, World},
+    texture::{ImageTexture, SolidTexture},
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, io::Error),
+    Image(PathBuf, ImageError),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "{}: {err}", path.display()),
+            Self::Image(path, err) => write!(f, "{}: {err}", path.display()),
+            Self::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{line}: {message}", path.display()),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(_, err) => Some(err),
+            Self::Image(_, err) => Some(err),
+            Self::Parse { .. } => None,
+        }
+    }
+}
+
+type SharedMaterial = Arc<dyn Material + Sync + Send>;
+
+/// Loads a Wavefront OBJ file along with the MTL libraries it references.
+///
+/// Every group and material combination becomes a [`TriangleMesh`] in the
+/// returned world. Meshes with an emissive material are pushed as lights.
+pub fn load(path: impl AsRef<Path>) -> Result<World, ObjError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|err| ObjError::Io(path.into(), err))?;
+
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<Vec2> = Vec::new();
+
+    let mut materials: HashMap<String, ObjMaterial> = HashMap::new();
+    let mut group = String::new();
+    let mut material: Option<String> = None;
+
+    let mut builders: Vec<MeshBuilder> = Vec::new();
+    let mut builder_indices: HashMap<(String, Option<String>), usize> = HashMap::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut parser = LineParser::new(path, index + 1, line);
+        let Some(keyword) = parser.keyword() else {
+            continue;
+        };
+
+        match keyword {
+            "v" => positions.push(parser.vec3()?),
+            "vn" => normals.push(parser.vec3()?),
+            "vt" => uvs.push(parser.uv()?),
+            "g" | "o" => group = parser.rest(),
+            "usemtl" => material = Some(parser.rest()),
+            "mtllib" => {
+                for library in parser.tokens.by_ref() {
+                    let library = path.with_file_name(library);
+                    materials.extend(load_mtl(&library)?);
+                }
+            }
+            "f" => {
+                let tokens: Vec<&str> = parser.tokens.by_ref().collect();
+                let corners = tokens
+                    .into_iter()
+                    .map(|corner| {
+                        parser.face_corner(corner, positions.len(), uvs.len(), normals.len())
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(parser.error("face needs at least three vertices"));
+                }
+
+                let key = (group.clone(), material.clone());
+                let builder = *builder_indices.entry(key).or_insert_with(|| {
+                    builders.push(MeshBuilder::new(material.clone()));
+                    builders.len() - 1
+                });
+                let builder = &mut builders[builder];
+
+                let corners: Vec<u32> = corners
+                    .into_iter()
+                    .map(|corner| builder.vertex(corner, &positions, &uvs, &normals))
+                    .collect();
+                for i in 1..corners.len() - 1 {
+                    builder
+                        .indices
+                        .push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut shared: HashMap<Option<String>, (SharedMaterial, bool)> = HashMap::new();
+    let mut textures: HashMap<PathBuf, Arc<ImageTexture>> = HashMap::new();
+    let mut world = World::new();
+
+    for builder in builders {
+        let (material, emissive) = match shared.get(&builder.material) {
+            Some(material) => material.clone(),
+            None => {
+                let obj_material = builder
+                    .material
+                    .as_ref()
+                    .and_then(|name| materials.get(name))
+                    .cloned()
+                    .unwrap_or_default();
+                let material = (
+                    obj_material.build(&mut textures)?,
+                    obj_material.is_emissive(),
+                );
+                shared.insert(builder.material.clone(), material.clone());
+                material
+            }
+        };
+
+        let mesh = TriangleMesh::new(builder.data(), material);
+        if emissive {
+            world.push_light(mesh);
+        } else {
+            world.push(mesh);
+        }
+    }
+
+    Ok(world)
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+    let source = fs::read_to_string(path).map_err(|err| ObjError::Io(path.into(), err))?;
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, ObjMaterial)> = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let mut parser = LineParser::new(path, index + 1, line);
+        let Some(keyword) = parser.keyword() else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            materials.extend(current.take());
+            current = Some((parser.rest(), ObjMaterial::default()));
+            continue;
+        }
+
+        let Some((_, material)) = current.as_mut() else {
+            continue;
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parser.vec3()?,
+            "Ks" => material.specular = parser.vec3()?,
+            "Ke" => material.emission = parser.vec3()?,
+            "Ns" => material.shininess = parser.number()?,
+            "Ni" => material.refraction_index = parser.number()?,
+            "d" => material.dissolve = parser.number()?,
+            "Tr" => material.dissolve = 1. - parser.number::<f32>()?,
+            "illum" => material.illum = parser.number()?,
+            "map_Kd" => {
+                // Options such as `-s 1 1 1` precede the file name.
+                let file = parser.tokens.by_ref().last();
+                let file = file.ok_or_else(|| parser.error("missing texture file name"))?;
+                material.diffuse_map = Some(path.with_file_name(file));
+            }
+            _ => {}
+        }
+    }
+
+    materials.extend(current);
+    Ok(materials)
+}
+
+#[derive(Clone)]
+struct ObjMaterial {
+    diffuse: Vec3,
+    specular: Vec3,
+    emission: Vec3,
+    shininess: f32,
+    refraction_index: f32,
+    dissolve: f32,
+    illum: u32,
+    diffuse_map: Option<PathBuf>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Vec3::splat(0.8),
+            specular: Vec3::ZERO,
+            emission: Vec3::ZERO,
+            shininess: 0.,
+            refraction_index: 1.,
+            dissolve: 1.,
+            illum: 2,
+            diffuse_map: None,
+        }
+    }
+}
+
+impl ObjMaterial {
+    fn is_emissive(&self) -> bool {
+        self.emission.max_element() > 0.
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.dissolve < 1. || matches!(self.illum, 4 | 6 | 7)
+    }
+
+    fn is_metallic(&self) -> bool {
+        self.illum == 3 || self.specular.max_element() > self.diffuse.max_element()
+    }
+
+    /// Maps the Phong shininess exponent onto the fuzz of [`Metal`].
+    fn roughness(&self) -> f32 {
+        (2. / (self.shininess + 2.)).sqrt().clamp(0., 1.)
+    }
+
+    fn build(
+        &self,
+        textures: &mut HashMap<PathBuf, Arc<ImageTexture>>,
+    ) -> Result<SharedMaterial, ObjError> {
+        let material: SharedMaterial = if self.is_emissive() {
+            Arc::new(DiffuseLight::from(color(self.emission)))
+        } else if self.is_transparent() {
+            let refraction_index = if self.refraction_index > 1. {
+                self.refraction_index
+            } else {
+                Dielectric::default().refraction_index
+            };
+            Arc::new(Dielectric::new(refraction_index))
+        } else if self.is_metallic() {
+            Arc::new(Metal::new(
+                SolidTexture::from(color(self.specular)),
+                self.roughness(),
+            ))
+        } else if let Some(path) = &self.diffuse_map {
+            let texture = match textures.get(path) {
+                Some(texture) => texture.clone(),
+                None => {
+                    let texture = ImageTexture::open(path)
+                        .map_err(|err| ObjError::Image(path.clone(), err))?;
+                    let texture = Arc::new(texture);
+                    textures.insert(path.clone(), texture.clone());
+                    texture
+                }
+            };
+            Arc::new(Lambertian::from(texture))
+        } else {
+            Arc::new(Lambertian::from(color(self.diffuse)))
+        };
+
+        Ok(material)
+    }
+}
+
+fn color(rgb: Vec3) -> Color {
+    LinearRgba::rgb(rgb.x, rgb.y, rgb.z).into()
+}
+
+/// Indices of a face corner into the position, uv and normal lists.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct MeshBuilder {
+    material: Option<String>,
+    vertices: HashMap<Corner, u32>,
+    positions: Vec<Vec3>,
+    uvs: Vec<Option<Vec2>>,
+    normals: Vec<Option<Vec3>>,
+    indices: Vec<[u32; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(material: Option<String>) -> Self {
+        Self {
+            material,
+            vertices: HashMap::new(),
+            positions: Vec::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn vertex(
+        &mut self,
+        corner: Corner,
+        positions: &[Vec3],
+        uvs: &[Vec2],
+        normals: &[Vec3],
+    ) -> u32 {
+        *self.vertices.entry(corner).or_insert_with(|| {
+            let (position, uv, normal) = corner;
+            self.positions.push(positions[position]);
+            self.uvs.push(uv.map(|uv| uvs[uv]));
+            self.normals.push(normal.map(|normal| normals[normal]));
+            self.positions.len() as u32 - 1
+        })
+    }
+
+    /// Normals and uvs are only kept when every vertex of the mesh has them.
+    fn data(self) -> TriangleMeshData {
+        TriangleMeshData {
+            positions: self.positions,
+            normals: self
+                .normals
+                .into_iter()
+                .collect::<Option<_>>()
+                .unwrap_or_default(),
+            uvs: self
+                .uvs
+                .into_iter()
+                .collect::<Option<_>>()
+                .unwrap_or_default(),
+            indices: self.indices,
+        }
+    }
+}
+
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+    tokens: SplitWhitespace<'a>,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(path: &'a Path, line: usize, source: &'a str) -> Self {
+        let source = source.split('#').next().unwrap_or_default();
+
+        Self {
+            path,
+            line,
+            tokens: source.split_whitespace(),
+        }
+    }
+
+    fn keyword(&mut self) -> Option<&'a str> {
+        self.tokens.next()
+    }
+
+    fn rest(&mut self) -> String {
+        self.tokens.by_ref().collect::<Vec<_>>().join(" ")
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, ObjError> {
+        let token = self
+            .tokens
+            .next()
+            .ok_or_else(|| self.error("missing number"))?;
+        token
+            .parse()
+            .map_err(|_| self.error(format!("invalid number `{token}`")))
+    }
+
+    /// Parses `u [v [w]]`, where `v` defaults to 0 and `w` is ignored.
+    fn uv(&mut self) -> Result<Vec2, ObjError> {
+        let u = self.number()?;
+        let v = match self.tokens.clone().next() {
+            Some(_) => self.number()?,
+            None => 0.,
+        };
+        Ok(Vec2::new(u, v))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, ObjError> {
+        Ok(Vec3::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    /// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner, resolving
+    /// negative indices relative to the end of each list.
+    fn face_corner(
+        &self,
+        corner: &str,
+        position_count: usize,
+        uv_count: usize,
+        normal_count: usize,
+    ) -> Result<Corner, ObjError> {
+        let mut parts = corner.split('/');
+
+        let position = parts
+            .next()
+            .filter(|part| !part.is_empty())
+            .ok_or_else(|| self.error(format!("invalid face corner `{corner}`")))?;
+        let position = self.index(position, position_count)?;
+
+        let uv = match parts.next().filter(|part| !part.is_empty()) {
+            Some(uv) => Some(self.index(uv, uv_count)?),
+            None => None,
+        };
+        let normal = match parts.next().filter(|part| !part.is_empty()) {
+            Some(normal) => Some(self.index(normal, normal_count)?),
+            None => None,
+        };
+
+        Ok((position, uv, normal))
+    }
+
+    fn index(&self, token: &str, count: usize) -> Result<usize, ObjError> {
+        let index: isize = token
+            .parse()
+            .map_err(|_| self.error(format!("invalid index `{token}`")))?;
+
+        let resolved = if index < 0 {
+            count as isize + index
+        } else {
+            index - 1
+        };
+
+        if (0..count as isize).contains(&resolved) {
+            Ok(resolved as usize)
+        } else {
+            Err(self.error(format!("index `{token}` out of range")))
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse {
+            path: self.path.into(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+    use crate::{mesh::Mesh, Ray};
+
+    /// Writes `files` to a fresh directory and loads the first one.
+    fn load_files(name: &str, files: &[(&str, &str)]) -> Result<World, ObjError> {
+        let dir = std::env::temp_dir().join(format!("obj-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        let world = load(dir.join(files[0].0));
+        fs::remove_dir_all(dir).unwrap();
+        world
+    }
+
+    /// Hit of a ray straight down the z axis through (`x`, `y`).
+    fn hit_at(world: &World, x: f32, y: f32) -> Option<(Vec3, Vec3, Vec2)> {
+        let ray = Ray::new(Vec3::new(x, y, 1.), Vec3::NEG_Z, 0.);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let hit = world.hit(&ray, &(0.001..f32::INFINITY).into(), &mut rng)?;
+        Some((hit.point, hit.normal, hit.uv))
+    }
+
+    fn parse_error(result: Result<World, ObjError>) -> (usize, String) {
+        match result {
+            Err(ObjError::Parse { line, message, .. }) => (line, message),
+            Err(err) => panic!("unexpected error {err}"),
+            Ok(_) => panic!("loaded invalid file"),
+        }
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let parser = LineParser::new(Path::new("test.obj"), 1, "");
+        assert_eq!(
+            parser.face_corner("-1/-2/-3", 4, 3, 3).unwrap(),
+            (3, Some(1), Some(0))
+        );
+        assert_eq!(
+            parser.face_corner("2//-1", 4, 0, 3).unwrap(),
+            (1, None, Some(2))
+        );
+
+        let world = load_files(
+            "negative",
+            &[(
+                "model.obj",
+                "v 5 5 5\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                 vt 0.5 0.5\nvt 0.5 0.5\nvt 0.5 0.5\n\
+                 vn 0.6 0 0.8\n\
+                 f -3/-3/-1 -2/-2/-1 -1/-1/-1\n",
+            )],
+        )
+        .unwrap();
+        let (point, normal, uv) = hit_at(&world, 0.2, 0.2).unwrap();
+        assert!(point.abs_diff_eq(Vec3::new(0.2, 0.2, 0.), 1e-5));
+        assert!(normal.abs_diff_eq(Vec3::new(0.6, 0., 0.8), 1e-5));
+        assert!(uv.abs_diff_eq(Vec2::splat(0.5), 1e-5));
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        // Convex pentagon around the origin.
+        let world = load_files(
+            "fan",
+            &[(
+                "model.obj",
+                "v 1 0 0\nv 0.3 1 0\nv -0.8 0.6 0\nv -0.8 -0.6 0\nv 0.3 -1 0\nf 1 2 3 4 5\n",
+            )],
+        )
+        .unwrap();
+
+        for (x, y) in [
+            (0., 0.),
+            (0.8, 0.),
+            (0.3, 0.8),
+            (-0.7, 0.5),
+            (-0.7, -0.5),
+            (0.3, -0.8),
+        ] {
+            assert!(hit_at(&world, x, y).is_some(), "missed ({x}, {y})");
+        }
+        for (x, y) in [(1.1, 0.), (-0.9, 0.), (0., 1.), (0., -1.)] {
+            assert!(hit_at(&world, x, y).is_none(), "hit ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn one_component_uvs_default_v_to_zero() {
+        let world = load_files(
+            "uv",
+            &[(
+                "model.obj",
+                "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.25\nf 1/1 2/1 3/1\n",
+            )],
+        )
+        .unwrap();
+        let (_, _, uv) = hit_at(&world, 0.2, 0.2).unwrap();
+        assert!(uv.abs_diff_eq(Vec2::new(0.25, 0.), 1e-5));
+    }
+
+    #[test]
+    fn emissive_materials_become_lights() {
+        let world = load_files(
+            "lights",
+            &[
+                (
+                    "model.obj",
+                    "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                     usemtl plain\nf 1 2 3\nusemtl glow\nf 3 2 1\n",
+                ),
+                (
+                    "model.mtl",
+                    "newmtl plain\nKd 0.5 0.5 0.5\nnewmtl glow\nKe 4 4 4\n",
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(world.len(), 2);
+        assert_eq!(world.lights().len(), 1);
+    }
+
+    #[test]
+    fn materials_may_be_used_before_their_library() {
+        let world = load_files(
+            "order",
+            &[
+                (
+                    "model.obj",
+                    "usemtl glow\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nmtllib lights.mtl\n",
+                ),
+                ("lights.mtl", "newmtl glow\nKe 1 1 1\n"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(world.lights().len(), 1);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_reported() {
+        let vertices = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\n";
+        for (face, token) in [
+            ("f 1 2 4", "4"),
+            ("f 0 1 2", "0"),
+            ("f -4 1 2", "-4"),
+            ("f 1/2 2/1 3/1", "2"),
+            ("f 1//1 2 3", "1"),
+        ] {
+            let source = format!("{vertices}{face}\n");
+            let (line, message) = parse_error(load_files("range", &[("model.obj", &source)]));
+            assert_eq!(line, 5, "{face}");
+            assert_eq!(message, format!("index `{token}` out of range"), "{face}");
+        }
+
+        let (line, message) = parse_error(load_files(
+            "short",
+            &[("model.obj", "v 0 0 0\nv 1 0 0\nf 1 2\n")],
+        ));
+        assert_eq!(
+            (line, message.as_str()),
+            (3, "face needs at least three vertices")
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::{framebuffer::Framebuffer, tile::RenderedTile};
+
+/// Receives the progress of renders, counted in samples.
+pub trait ProgressReporter: Send + Sync {
+    /// A render of `total` samples starts, of which `done` were taken before,
+    /// for example by the render a checkpoint was made of. `total` is `None`
+    /// when a time budget decides when the render ends.
+    fn start(&self, total: Option<u64>, done: u64);
+
+    /// `samples` more samples were taken.
+    fn advance(&self, samples: u64);
+
+    fn finish(&self);
+}
+
+impl ProgressReporter for ProgressBar {
+    fn start(&self, total: Option<u64>, done: u64) {
+        match total {
+            Some(total) => self.set_length(total),
+            None => self.set_style(ProgressStyle::default_spinner()),
+        }
+        self.set_position(done);
+        self.reset_eta();
+    }
+
+    fn advance(&self, samples: u64) {
+        self.inc(samples);
+    }
+
+    fn finish(&self) {
+        ProgressBar::finish(self);
+    }
+}
+
+/// Snapshot of how far a render has come.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub samples_done: u64,
+    /// `None` when a time budget decides when the render ends.
+    pub samples_total: Option<u64>,
+    pub elapsed: Duration,
+    /// Time left at the pace of the samples taken since the render started.
+    pub eta: Option<Duration>,
+    pub finished: bool,
+}
+
+/// Shared view of the renders of a camera, through which other threads can
+/// follow their progress, look at the image rendered so far and cancel them.
+/// Clones refer to the same renders.
+#[derive(Clone, Default)]
+pub struct RenderHandle {
+    state: Arc<HandleState>,
+}
+
+#[derive(Default)]
+struct HandleState {
+    cancelled: AtomicBool,
+    samples_done: AtomicU64,
+    run: Mutex<Run>,
+}
+
+#[derive(Default)]
+struct Run {
+    samples_total: Option<u64>,
+    /// Samples that were already taken when the render started.
+    samples_initial: u64,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    framebuffer: Option<Framebuffer>,
+}
+
+impl RenderHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the current render after the tiles being rendered, leaving it
+    /// with the samples taken so far. Between renders, the next one stops
+    /// before taking any samples. Renders after the stopped one run as usual.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Progress of the current render, or the last one once it finished.
+    pub fn progress(&self) -> Progress {
+        let run = self.state.run.lock().unwrap();
+        let samples_done = self.state.samples_done.load(Ordering::Relaxed);
+        let elapsed = match (run.started, run.finished) {
+            (Some(started), Some(finished)) => finished - started,
+            (Some(started), None) => started.elapsed(),
+            (None, _) => Duration::ZERO,
+        };
+
+        let new_samples = samples_done.saturating_sub(run.samples_initial);
+        let eta = match run.samples_total {
+            _ if run.finished.is_some() => Some(Duration::ZERO),
+            Some(total) if new_samples > 0 => {
+                let pace = elapsed.as_secs_f64() / new_samples as f64;
+                Some(Duration::from_secs_f64(
+                    total.saturating_sub(samples_done) as f64 * pace,
+                ))
+            }
+            _ => None,
+        };
+
+        Progress {
+            samples_done,
+            samples_total: run.samples_total,
+            elapsed,
+            eta,
+            finished: run.finished.is_some(),
+        }
+    }
+
+    /// Image rendered so far by the current render, or the last one once it
+    /// finished. `None` before the first render starts.
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        self.state.run.lock().unwrap().framebuffer.clone()
+    }
+
+    pub(crate) fn start(&self, total: Option<u64>, framebuffer: Framebuffer, done: u64) {
+        let mut run = self.state.run.lock().unwrap();
+        *run = Run {
+            samples_total: total,
+            samples_initial: done,
+            started: Some(Instant::now()),
+            finished: None,
+            framebuffer: Some(framebuffer),
+        };
+        self.state.samples_done.store(done, Ordering::Relaxed);
+    }
+
+    pub(crate) fn update(&self, tile: &RenderedTile, samples: u64) {
+        self.state
+            .samples_done
+            .fetch_add(samples, Ordering::Relaxed);
+
+        let mut run = self.state.run.lock().unwrap();
+        if let Some(framebuffer) = &mut run.framebuffer {
+            for ((x, y), &color) in tile.tile.pixels().zip(&tile.pixels) {
+                framebuffer.set(x, y, color);
+            }
+        }
+    }
+
+    pub(crate) fn finish(&self) {
+        self.state.run.lock().unwrap().finished = Some(Instant::now());
+        self.state.cancelled.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,533 @@
+use bevy_math::Vec2;
+use clap::ValueEnum;
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
+use serde::Deserialize;
+
+/// Largest `f32` below 1.
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON / 2.;
+
+/// Bases of the Halton dimensions; later dimensions fall back to random numbers.
+const PRIMES: [u32; 128] = primes();
+
+/// Source of the numbers driving each sample of a pixel.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SamplerKind {
+    /// Independent uniform random numbers.
+    Random,
+    /// Jittered samples spread over the strata of every dimension.
+    Stratified,
+    /// Owen-scrambled Halton sequence.
+    Halton,
+    /// Owen-scrambled Sobol sequence, padded across pairs of dimensions.
+    #[default]
+    Sobol,
+}
+
+impl SamplerKind {
+    /// Sampler drawing `samples_per_pixel` samples per pixel, reproducible for
+    /// a given `seed`.
+    pub fn build(self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            Self::Random => Box::new(RandomSampler::new(seed)),
+            Self::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            Self::Halton => Box::new(HaltonSampler::new(seed)),
+            Self::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+/// Sequence of sample points, each a series of dimensions uniform in `[0, 1)`.
+///
+/// The camera draws the pixel offset, the lens position and the time from
+/// the first dimensions of every sample; scattering and light sampling draw
+/// the rest through [`SamplerRng`].
+pub trait Sampler {
+    /// Moves to sample `index` of the pixel at (`x`, `y`), starting over
+    /// from its first dimension.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: usize);
+
+    /// Next dimension of the current sample.
+    fn get_1d(&mut self) -> f32;
+
+    /// Next two dimensions of the current sample.
+    fn get_2d(&mut self) -> Vec2;
+}
+
+/// Draws the dimensions of a [`Sampler`] through the [`RngCore`] interface
+/// meshes and materials sample with, one dimension per `u32`.
+pub struct SamplerRng<'a>(pub &'a mut dyn Sampler);
+
+impl RngCore for SamplerRng<'_> {
+    fn next_u32(&mut self) -> u32 {
+        (self.0.get_1d() as f64 * 2f64.powi(32)) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        // The first dimension provides the high bits, which `f64` samples keep.
+        let high = self.next_u32() as u64;
+        high << 32 | self.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+pub struct RandomSampler {
+    seed: u64,
+    rng: SmallRng,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: usize) {
+        let hash = mix(pixel_hash(self.seed, x, y), index as u64);
+        self.rng = SmallRng::seed_from_u64(hash);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        Vec2::new(self.rng.gen(), self.rng.gen())
+    }
+}
+
+/// Places the samples of a pixel in distinct strata of every dimension, or
+/// of a square grid for pairs of dimensions, jittered within the stratum.
+/// Each dimension visits the strata in its own random order.
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.clamp(1, u32::MAX as usize) as u32,
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Stratum of the current sample among `count`, and the jitter within it.
+    fn stratum(&mut self, count: u32) -> (u32, u64) {
+        let hash = mix(self.pixel, self.dimension);
+        // Samples beyond `count` start another round over the strata.
+        let round = mix(hash, (self.index / count) as u64);
+        let stratum = permutation_element(self.index % count, count, round as u32);
+        (stratum, mix(round, self.index as u64))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: usize) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (stratum, jitter) = self.stratum(self.samples_per_pixel);
+        self.dimension += 1;
+
+        ((stratum as f32 + unit_float(jitter)) / self.samples_per_pixel as f32)
+            .min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let side = (self.samples_per_pixel as f32).sqrt() as u32;
+        let (stratum, jitter) = self.stratum(side * side);
+        self.dimension += 2;
+
+        let cell = Vec2::new((stratum % side) as f32, (stratum / side) as f32);
+        let jitter = Vec2::new(unit_float(jitter), unit_float(mix(jitter, 1)));
+        ((cell + jitter) / side as f32).min(Vec2::splat(ONE_MINUS_EPSILON))
+    }
+}
+
+/// Radical inverses of the sample index in successive prime bases, with the
+/// digits Owen-scrambled differently for every pixel.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: usize) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let hash = mix(self.pixel, self.dimension as u64);
+        let value = match PRIMES.get(self.dimension) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.index, hash),
+            None => unit_float(mix(hash, self.index)),
+        };
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        Vec2::new(self.get_1d(), self.get_1d())
+    }
+}
+
+/// The first two dimensions of the Sobol sequence, which stratify every
+/// power-of-two number of samples, reused for every pair of dimensions.
+/// Each pair is Owen-scrambled and visits the points in its own order,
+/// after Burley's "Practical Hash-based Owen Scrambling".
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Index of the current sample in the shuffled order of the next
+    /// dimensions, and the seed scrambling them.
+    fn shuffle(&mut self, dimensions: u64) -> (u32, u64) {
+        let hash = mix(self.pixel, self.dimension);
+        self.dimension += dimensions;
+        (nested_uniform_scramble(self.index, hash as u32), hash)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: usize) {
+        self.pixel = pixel_hash(self.seed, x, y);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let (index, hash) = self.shuffle(1);
+        scrambled_to_float(index.reverse_bits(), mix(hash, 1))
+    }
+
+    fn get_2d(&mut self) -> Vec2 {
+        let (index, hash) = self.shuffle(2);
+        Vec2::new(
+            scrambled_to_float(index.reverse_bits(), mix(hash, 1)),
+            scrambled_to_float(sobol_second_dimension(index), mix(hash, 2)),
+        )
+    }
+}
+
+/// Combines `value` into the hash `state`, after SplitMix64.
+pub(crate) fn mix(state: u64, value: u64) -> u64 {
+    let mut z = (state ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce5_e4b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn pixel_hash(seed: u64, x: u32, y: u32) -> u64 {
+    mix(mix(seed, x as u64), y as u64)
+}
+
+/// Uniform float in `[0, 1)` from the high bits of `hash`.
+fn unit_float(hash: u64) -> f32 {
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Element `index` of a random permutation of `0..count` chosen by `seed`,
+/// after Kensler's "Correlated Multi-Jittered Sampling".
+fn permutation_element(mut index: u32, count: u32, seed: u32) -> u32 {
+    let mut mask = count.saturating_sub(1);
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+
+        if index < count {
+            break index.wrapping_add(seed) % count;
+        }
+    }
+}
+
+/// Radical inverse of `index` in `base` with every digit permuted depending
+/// on the digits below it, so that the result stays well stratified.
+fn owen_scrambled_radical_inverse(base: u32, mut index: u64, hash: u64) -> f32 {
+    let inverse_base = 1. / base as f32;
+    let mut inverse_base_power = 1f32;
+    let mut reversed_digits = 0u64;
+
+    // Scramble every digit `f32` can resolve, including the leading zeros.
+    while 1. - (base - 1) as f32 * inverse_base_power < 1. {
+        let next = index / base as u64;
+        let digit = (index - next * base as u64) as u32;
+        let digit_hash = mix(hash, reversed_digits) as u32;
+        let digit = permutation_element(digit, base, digit_hash);
+
+        reversed_digits = reversed_digits * base as u64 + digit as u64;
+        inverse_base_power *= inverse_base;
+        index = next;
+    }
+
+    (reversed_digits as f32 * inverse_base_power).min(ONE_MINUS_EPSILON)
+}
+
+/// Second dimension of the Sobol sequence, as bits of the binary fraction.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Bijection on `u32` in which every bit only depends on itself and the bits
+/// below it, after Laine and Karras.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// Owen scrambling of the binary fraction `x`.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn scrambled_to_float(x: u32, hash: u64) -> f32 {
+    let x = nested_uniform_scramble(x, hash as u32);
+    (x as f32 / 2f32.powi(32)).min(ONE_MINUS_EPSILON)
+}
+
+const fn primes<const N: usize>() -> [u32; N] {
+    let mut primes = [0; N];
+    let mut count = 0;
+    let mut candidate = 2;
+    while count < N {
+        let mut divisor = 2;
+        while divisor * divisor <= candidate && candidate % divisor != 0 {
+            divisor += 1;
+        }
+        if divisor * divisor > candidate {
+            primes[count] = candidate;
+            count += 1;
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Random,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    /// Pair of dimensions after `pair` others of the first `count` samples
+    /// of a pixel.
+    fn points(sampler: &mut dyn Sampler, count: usize, pair: usize) -> Vec<Vec2> {
+        (0..count)
+            .map(|index| {
+                sampler.start_pixel_sample(3, 5, index);
+                for _ in 0..pair {
+                    sampler.get_2d();
+                }
+                sampler.get_2d()
+            })
+            .collect()
+    }
+
+    /// Whether `points` put exactly one point in every cell of a `columns`
+    /// by `rows` grid.
+    fn fills_grid(points: &[Vec2], columns: u32, rows: u32) -> bool {
+        let mut counts = vec![0; (columns * rows) as usize];
+        for point in points {
+            let column = (point.x * columns as f32) as u32;
+            let row = (point.y * rows as f32) as u32;
+            counts[(row * columns + column) as usize] += 1;
+        }
+        counts.iter().all(|&count| count == 1)
+    }
+
+    #[test]
+    fn samples_lie_in_unit_interval() {
+        for kind in KINDS {
+            let mut sampler = kind.build(64, 7);
+            for index in 0..256 {
+                sampler.start_pixel_sample(1, 2, index);
+                for _ in 0..8 {
+                    let point = sampler.get_2d();
+                    let value = sampler.get_1d();
+                    assert!([point.x, point.y, value]
+                        .iter()
+                        .all(|value| (0. ..1.).contains(value)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_depend_only_on_seed_pixel_and_index() {
+        for kind in KINDS {
+            let draw = |seed: u64, x: u32, index: usize| {
+                let mut sampler = kind.build(16, seed);
+                sampler.start_pixel_sample(x, 0, index);
+                (sampler.get_2d(), sampler.get_1d())
+            };
+
+            assert_eq!(draw(1, 2, 3), draw(1, 2, 3));
+            assert_ne!(draw(1, 2, 3), draw(2, 2, 3));
+            assert_ne!(draw(1, 2, 3), draw(1, 4, 3));
+        }
+    }
+
+    #[test]
+    fn stratified_sampler_fills_every_stratum() {
+        let mut sampler = StratifiedSampler::new(16, 0);
+        for pair in 0..4 {
+            assert!(fills_grid(&points(&mut sampler, 16, pair), 4, 4));
+        }
+
+        for dimension in 0..4 {
+            let values: Vec<Vec2> = (0..16)
+                .map(|index| {
+                    sampler.start_pixel_sample(3, 5, index);
+                    for _ in 0..dimension {
+                        sampler.get_1d();
+                    }
+                    Vec2::new(sampler.get_1d(), 0.)
+                })
+                .collect();
+            assert!(fills_grid(&values, 16, 1));
+        }
+    }
+
+    #[test]
+    fn halton_sampler_fills_elementary_intervals() {
+        let mut sampler = HaltonSampler::new(0);
+        // Bases 2 and 3, then 5 and 7.
+        assert!(fills_grid(&points(&mut sampler, 36, 0), 4, 9));
+        assert!(fills_grid(&points(&mut sampler, 35, 1), 5, 7));
+    }
+
+    #[test]
+    fn sobol_sampler_fills_elementary_intervals() {
+        let mut sampler = SobolSampler::new(0);
+        for pair in 0..4 {
+            let points = points(&mut sampler, 64, pair);
+            for bits in 0..=6 {
+                assert!(fills_grid(&points, 1 << bits, 1 << (6 - bits)));
+            }
+        }
+    }
+
+    #[test]
+    fn low_discrepancy_samplers_integrate_better_than_random() {
+        // Mean of (x + y)^2 over the unit square.
+        let exact = 7. / 6.;
+        let rms_error = |kind: SamplerKind| {
+            let mut sampler = kind.build(64, 0);
+            let squared_error: f32 = (0..64)
+                .map(|x| {
+                    let estimate = (0..64)
+                        .map(|index| {
+                            sampler.start_pixel_sample(x, 0, index);
+                            let point = sampler.get_2d();
+                            (point.x + point.y).powi(2)
+                        })
+                        .sum::<f32>()
+                        / 64.;
+                    (estimate - exact).powi(2)
+                })
+                .sum();
+            (squared_error / 64.).sqrt()
+        };
+
+        let random = rms_error(SamplerKind::Random);
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            assert!(rms_error(kind) < random / 4.);
+        }
+    }
+}
@@ -0,0 +1,844 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use bevy_color::Color;
+use bevy_math::{Affine3A, EulerRot, Mat4, Quat, Vec3};
+use image::ImageError;
+use rand::{rngs::SmallRng, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    animation::{Animation, CameraKeyframe},
+    camera::{CameraConfig, Integrator, Shutter, SHUTTER_RANGE},
+    material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal},
+    mesh::{
+        AnimatedTransform, Bvh, ConstantMedium, Cube, Instance, Keyframe, Mesh, Quad, Sphere,
+        Transform, Triangle, World,
+    },
+    obj::{self, ObjError},
+    sampler::SamplerKind,
+    texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidTexture, Texture},
+    tile::TileOrder,
+    tonemap::ToneMapping,
+    utils::degrees_to_radians,
+};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Toml(PathBuf, toml::de::Error),
+    Image(PathBuf, ImageError),
+    Obj(ObjError),
+    UnknownTexture(String),
+    UnknownMaterial(String),
+    UnknownPrototype(String),
+    MissingMaterial,
+    /// A shutter time lies outside [`SHUTTER_RANGE`].
+    ShutterTime(f32),
+    /// The shutter closes, the second time, before it opens, the first.
+    InvertedShutter(f32, f32),
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "{}: {err}", path.display()),
+            Self::Toml(path, err) => write!(f, "{}: {err}", path.display()),
+            Self::Image(path, err) => write!(f, "{}: {err}", path.display()),
+            Self::Obj(err) => write!(f, "{err}"),
+            Self::UnknownTexture(name) => write!(f, "unknown texture `{name}`"),
+            Self::UnknownMaterial(name) => write!(f, "unknown material `{name}`"),
+            Self::UnknownPrototype(name) => write!(f, "unknown prototype `{name}`"),
+            Self::MissingMaterial => write!(f, "object is missing a material"),
+            Self::ShutterTime(time) => write!(f, "shutter time {time} is outside [0, 1]"),
+            Self::InvertedShutter(open, close) => {
+                write!(f, "shutter closes at {close} before it opens at {open}")
+            }
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(_, err) => Some(err),
+            Self::Toml(_, err) => Some(err),
+            Self::Image(_, err) => Some(err),
+            Self::Obj(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(err: ObjError) -> Self {
+        Self::Obj(err)
+    }
+}
+
+/// A scene loaded from a description file.
+pub struct Scene {
+    pub camera: CameraConfig,
+    pub world: World,
+    pub animation: Option<Animation>,
+}
+
+/// Loads a TOML scene description. Relative paths inside the file are
+/// resolved against the directory containing it.
+///
+/// Objects whose material is a `diffuse_light` are pushed as lights.
+/// Prototypes are groups of objects that are only rendered through
+/// `instance` objects, each placing the whole group with its own transform
+/// and, optionally, a material replacing all of the group's materials.
+pub fn load(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|err| SceneError::Io(path.into(), err))?;
+    let description: SceneDescription =
+        toml::from_str(&source).map_err(|err| SceneError::Toml(path.into(), err))?;
+
+    description.build(path.parent().unwrap_or(Path::new("")))
+}
+
+type SharedTexture = Arc<dyn Texture + Sync + Send>;
+type SharedMaterial = Arc<dyn Material + Sync + Send>;
+type SharedMesh = Arc<dyn Mesh + Sync + Send>;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    #[serde(default)]
+    camera: CameraDescription,
+    #[serde(default)]
+    textures: HashMap<String, TextureDescription>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDescription>,
+    #[serde(default)]
+    prototypes: HashMap<String, Vec<ObjectDescription>>,
+    #[serde(default)]
+    objects: Vec<ObjectDescription>,
+    animation: Option<AnimationDescription>,
+}
+
+impl SceneDescription {
+    fn build(self, base: &Path) -> Result<Scene, SceneError> {
+        let mut textures = HashMap::new();
+        for (name, texture) in self.textures {
+            let texture = texture.build(&name, base)?;
+            textures.insert(name, texture);
+        }
+
+        let mut materials = HashMap::new();
+        for (name, material) in self.materials {
+            let emissive = matches!(material, MaterialDescription::DiffuseLight { .. });
+            materials.insert(name, (material.build(&textures)?, emissive));
+        }
+
+        // Prototypes cannot instance each other.
+        let mut prototypes = HashMap::new();
+        for (name, objects) in self.prototypes {
+            let mut group = World::new();
+            for object in objects {
+                object.build(base, &materials, &HashMap::new(), &mut group)?;
+            }
+            let lights = group.lights();
+            let prototype = Prototype {
+                mesh: Arc::new(Bvh::from(&group)),
+                lights: (!lights.is_empty()).then(|| Arc::new(lights) as SharedMesh),
+            };
+            prototypes.insert(name, prototype);
+        }
+
+        let mut world = World::new();
+        for object in self.objects {
+            object.build(base, &materials, &prototypes, &mut world)?;
+        }
+
+        let camera = self.camera.build();
+        for time in [camera.shutter_open, camera.shutter_close] {
+            if !SHUTTER_RANGE.contains(&time) {
+                return Err(SceneError::ShutterTime(time));
+            }
+        }
+        if camera.shutter_close < camera.shutter_open {
+            return Err(SceneError::InvertedShutter(
+                camera.shutter_open,
+                camera.shutter_close,
+            ));
+        }
+        let animation = self.animation.map(|animation| animation.build(&camera));
+
+        Ok(Scene {
+            camera,
+            world,
+            animation,
+        })
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    width: Option<u32>,
+    height: Option<u32>,
+    samples_per_pixel: Option<usize>,
+    noise_threshold: Option<f32>,
+    /// Seconds.
+    time_budget: Option<f64>,
+    max_depth: Option<usize>,
+    roulette_depth: Option<usize>,
+    vfov: Option<f32>,
+    lookfrom: Option<[f32; 3]>,
+    lookat: Option<[f32; 3]>,
+    vup: Option<[f32; 3]>,
+    defocus_angle: Option<f32>,
+    focus_dist: Option<f32>,
+    background: Option<[f32; 3]>,
+    integrator: Option<Integrator>,
+    sampler: Option<SamplerKind>,
+    seed: Option<u64>,
+    shutter_open: Option<f32>,
+    shutter_close: Option<f32>,
+    shutter: Option<Shutter>,
+    exposure: Option<f32>,
+    white_balance: Option<f32>,
+    tone_mapping: Option<ToneMapping>,
+    tile_size: Option<u32>,
+    tile_order: Option<TileOrder>,
+}
+
+impl CameraDescription {
+    fn build(self) -> CameraConfig {
+        let default = CameraConfig::default();
+
+        CameraConfig {
+            width: self.width.unwrap_or(default.width),
+            height: self.height.unwrap_or(default.height),
+            samples_per_pixel: self.samples_per_pixel.unwrap_or(default.samples_per_pixel),
+            noise_threshold: self.noise_threshold.or(default.noise_threshold),
+            time_budget: self
+                .time_budget
+                .map(Duration::from_secs_f64)
+                .or(default.time_budget),
+            max_depth: self.max_depth.unwrap_or(default.max_depth),
+            roulette_depth: self.roulette_depth.unwrap_or(default.roulette_depth),
+            vfov: self.vfov.unwrap_or(default.vfov),
+            lookfrom: self.lookfrom.map_or(default.lookfrom, Vec3::from),
+            lookat: self.lookat.map_or(default.lookat, Vec3::from),
+            vup: self.vup.map_or(default.vup, Vec3::from),
+            defocus_angle: self.defocus_angle.unwrap_or(default.defocus_angle),
+            focus_dist: self.focus_dist.unwrap_or(default.focus_dist),
+            background: self.background.map_or(default.background, color),
+            integrator: self.integrator.unwrap_or(default.integrator),
+            sampler: self.sampler.unwrap_or(default.sampler),
+            seed: self.seed.unwrap_or(default.seed),
+            shutter_open: self.shutter_open.unwrap_or(default.shutter_open),
+            shutter_close: self.shutter_close.unwrap_or(default.shutter_close),
+            shutter: self.shutter.unwrap_or(default.shutter),
+            exposure: self.exposure.unwrap_or(default.exposure),
+            white_balance: self.white_balance.unwrap_or(default.white_balance),
+            tone_mapping: self.tone_mapping.unwrap_or(default.tone_mapping),
+            tile_size: self.tile_size.unwrap_or(default.tile_size),
+            tile_order: self.tile_order.unwrap_or(default.tile_order),
+        }
+    }
+}
+
+/// Frames `start..end`, measured in the same unit as object keyframe times.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationDescription {
+    #[serde(default)]
+    start: u32,
+    end: u32,
+    #[serde(default)]
+    camera: Vec<CameraKeyframeDescription>,
+}
+
+impl AnimationDescription {
+    fn build(self, config: &CameraConfig) -> Animation {
+        let keyframes = self
+            .camera
+            .into_iter()
+            .map(|keyframe| keyframe.build(config))
+            .collect();
+
+        Animation::new(self.start..self.end).with_camera(keyframes)
+    }
+}
+
+/// Camera placement at `frame`, keeping the `[camera]` values left out.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraKeyframeDescription {
+    frame: f32,
+    lookfrom: Option<[f32; 3]>,
+    lookat: Option<[f32; 3]>,
+    vfov: Option<f32>,
+    focus_dist: Option<f32>,
+}
+
+impl CameraKeyframeDescription {
+    fn build(self, config: &CameraConfig) -> CameraKeyframe {
+        let default = CameraKeyframe::from_config(self.frame, config);
+
+        CameraKeyframe {
+            frame: self.frame,
+            lookfrom: self.lookfrom.map_or(default.lookfrom, Vec3::from),
+            lookat: self.lookat.map_or(default.lookat, Vec3::from),
+            vfov: self.vfov.unwrap_or(default.vfov),
+            focus_dist: self.focus_dist.unwrap_or(default.focus_dist),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TextureDescription {
+    Solid {
+        color: [f32; 3],
+    },
+    Checker {
+        #[serde(default = "default_scale")]
+        scale: f32,
+        even: [f32; 3],
+        odd: [f32; 3],
+    },
+    Image {
+        path: PathBuf,
+    },
+    Noise {
+        #[serde(default = "default_scale")]
+        scale: f32,
+    },
+}
+
+fn default_scale() -> f32 {
+    1.
+}
+
+impl TextureDescription {
+    fn build(self, name: &str, base: &Path) -> Result<SharedTexture, SceneError> {
+        let texture: SharedTexture = match self {
+            Self::Solid { color: rgb } => Arc::new(SolidTexture::from(color(rgb))),
+            Self::Checker { scale, even, odd } => Arc::new(CheckerTexture {
+                scale,
+                even: SolidTexture::from(color(even)),
+                odd: SolidTexture::from(color(odd)),
+            }),
+            Self::Image { path } => {
+                let path = base.join(path);
+                let texture =
+                    ImageTexture::open(&path).map_err(|err| SceneError::Image(path, err))?;
+                Arc::new(texture)
+            }
+            Self::Noise { scale } => {
+                // Seeded by name, so the pattern is the same on every load.
+                let mut hasher = DefaultHasher::new();
+                name.hash(&mut hasher);
+                let rng = SmallRng::seed_from_u64(hasher.finish());
+                Arc::new(NoiseTexture::<256>::new(rng, scale))
+            }
+        };
+
+        Ok(texture)
+    }
+}
+
+/// Surface color, given either inline or as the name of a texture.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorDescription {
+    Rgb([f32; 3]),
+    Texture(String),
+}
+
+impl ColorDescription {
+    fn build(self, textures: &HashMap<String, SharedTexture>) -> Result<SharedTexture, SceneError> {
+        match self {
+            Self::Rgb(rgb) => Ok(Arc::new(SolidTexture::from(color(rgb)))),
+            Self::Texture(name) => textures
+                .get(&name)
+                .cloned()
+                .ok_or(SceneError::UnknownTexture(name)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MaterialDescription {
+    Lambertian {
+        color: ColorDescription,
+    },
+    Metal {
+        color: ColorDescription,
+        #[serde(default)]
+        roughness: f32,
+    },
+    Dielectric {
+        #[serde(default = "default_refraction_index")]
+        refraction_index: f32,
+    },
+    DiffuseLight {
+        color: ColorDescription,
+    },
+    Isotropic {
+        color: ColorDescription,
+    },
+}
+
+fn default_refraction_index() -> f32 {
+    Dielectric::default().refraction_index
+}
+
+impl MaterialDescription {
+    fn build(
+        self,
+        textures: &HashMap<String, SharedTexture>,
+    ) -> Result<SharedMaterial, SceneError> {
+        let material: SharedMaterial = match self {
+            Self::Lambertian { color } => Arc::new(Lambertian::from(color.build(textures)?)),
+            Self::Metal { color, roughness } => {
+                Arc::new(Metal::new(color.build(textures)?, roughness))
+            }
+            Self::Dielectric { refraction_index } => Arc::new(Dielectric::new(refraction_index)),
+            Self::DiffuseLight { color } => Arc::new(DiffuseLight::from(color.build(textures)?)),
+            Self::Isotropic { color } => Arc::new(Isotropic::from(color.build(textures)?)),
+        };
+
+        Ok(material)
+    }
+}
+
+#[derive(Deserialize)]
+struct ObjectDescription {
+    #[serde(flatten)]
+    shape: ShapeDescription,
+    material: Option<String>,
+    /// Applied in order, so `[{ rotate_y = 15 }, { translate = [1, 0, 0] }]`
+    /// rotates the object before moving it.
+    #[serde(default)]
+    transform: Vec<TransformDescription>,
+    /// Motion applied after `transform`, interpolated by ray time.
+    #[serde(default)]
+    keyframes: Vec<KeyframeDescription>,
+}
+
+impl ObjectDescription {
+    fn build(
+        self,
+        base: &Path,
+        materials: &HashMap<String, (SharedMaterial, bool)>,
+        prototypes: &HashMap<String, Prototype>,
+        world: &mut World,
+    ) -> Result<(), SceneError> {
+        // OBJ files bring their own materials and lights. A transformed model
+        // has its lights sampled through the same transform.
+        if let ShapeDescription::Obj { path } = &self.shape {
+            let model = obj::load(base.join(path))?;
+            if self.transform.is_empty() && self.keyframes.is_empty() {
+                world.append(model);
+            } else {
+                let place =
+                    |mesh: SharedMesh| animate(transform(mesh, &self.transform), &self.keyframes);
+                let lights = model.lights();
+                if !lights.is_empty() {
+                    world.push_light_source(place(Arc::new(lights)));
+                }
+                world.push(place(Arc::new(model)));
+            }
+            return Ok(());
+        }
+
+        let material = |name: &String| {
+            materials
+                .get(name)
+                .cloned()
+                .ok_or_else(|| SceneError::UnknownMaterial(name.clone()))
+        };
+
+        if let ShapeDescription::Instance { prototype } = &self.shape {
+            let Prototype { mesh, mut lights } = find_prototype(prototypes, prototype)?;
+            let matrix = matrix(&self.transform);
+            let mut instance = Instance::new(mesh, matrix);
+            let mut emissive = false;
+            if let Some(name) = &self.material {
+                let (material, is_emissive) = material(name)?;
+                instance = instance.with_material(material);
+                emissive = is_emissive;
+                // The override replaces the materials the prototype emits with.
+                lights = None;
+            }
+
+            let instance = animate(Arc::new(instance), &self.keyframes);
+            if emissive {
+                world.push_light(instance);
+            } else {
+                world.push(instance);
+            }
+            if let Some(lights) = lights {
+                let lights = Arc::new(Instance::new(lights, matrix));
+                world.push_light_source(animate(lights, &self.keyframes));
+            }
+            return Ok(());
+        }
+
+        let (material, emissive) = match &self.material {
+            Some(name) => material(name)?,
+            None if matches!(self.shape, ShapeDescription::Medium { .. }) => {
+                (Arc::new(Dielectric::default()) as SharedMaterial, false)
+            }
+            None => return Err(SceneError::MissingMaterial),
+        };
+
+        let mesh = animate(
+            transform(
+                self.shape.build(base, material, prototypes)?,
+                &self.transform,
+            ),
+            &self.keyframes,
+        );
+        if emissive {
+            world.push_light(mesh);
+        } else {
+            world.push(mesh);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ShapeDescription {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        /// Center at time 1, for motion blur. The sphere rests there in
+        /// later animation frames.
+        moving_to: Option<[f32; 3]>,
+    },
+    Quad {
+        corner: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+    },
+    Triangle {
+        a: [f32; 3],
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+    Cube {
+        min: [f32; 3],
+        max: [f32; 3],
+    },
+    Obj {
+        path: PathBuf,
+    },
+    /// Copy of a prototype. Only its geometry is used as a medium boundary.
+    Instance {
+        prototype: String,
+    },
+    /// Participating medium filling `boundary`, scattering with `color`.
+    Medium {
+        boundary: Box<ShapeDescription>,
+        density: f32,
+        color: [f32; 3],
+    },
+}
+
+impl ShapeDescription {
+    fn build(
+        self,
+        base: &Path,
+        material: SharedMaterial,
+        prototypes: &HashMap<String, Prototype>,
+    ) -> Result<SharedMesh, SceneError> {
+        let mesh: SharedMesh = match self {
+            Self::Sphere {
+                center,
+                radius,
+                moving_to: None,
+            } => Arc::new(Sphere::stationary(center.into(), radius, material)),
+            Self::Sphere {
+                center,
+                radius,
+                moving_to: Some(to),
+            } => Arc::new(Sphere::moving(center.into(), to.into(), radius, material)),
+            Self::Quad { corner, u, v } => {
+                Arc::new(Quad::new(corner.into(), u.into(), v.into(), material))
+            }
+            Self::Triangle { a, b, c } => {
+                Arc::new(Triangle::new(a.into(), b.into(), c.into(), material))
+            }
+            Self::Cube { min, max } => Arc::new(Cube::new(min.into(), max.into(), material)),
+            Self::Obj { path } => Arc::new(obj::load(base.join(path))?),
+            Self::Instance { prototype } => find_prototype(prototypes, &prototype)?.mesh,
+            Self::Medium {
+                boundary,
+                density,
+                color: rgb,
+            } => Arc::new(ConstantMedium::from_color(
+                boundary.build(base, material, prototypes)?,
+                density,
+                color(rgb),
+            )),
+        };
+
+        Ok(mesh)
+    }
+}
+
+/// Rotations are in degrees.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransformDescription {
+    Translate([f32; 3]),
+    RotateX(f32),
+    RotateY(f32),
+    RotateZ(f32),
+    Rotate {
+        axis: [f32; 3],
+        angle: f32,
+    },
+    Scale([f32; 3]),
+    /// Rows of an affine 4x4 matrix, whose last row is ignored.
+    Matrix([[f32; 4]; 4]),
+}
+
+impl TransformDescription {
+    fn matrix(&self) -> Affine3A {
+        match *self {
+            Self::Translate(offset) => Affine3A::from_translation(offset.into()),
+            Self::RotateX(angle) => Affine3A::from_rotation_x(degrees_to_radians(angle)),
+            Self::RotateY(angle) => Affine3A::from_rotation_y(degrees_to_radians(angle)),
+            Self::RotateZ(angle) => Affine3A::from_rotation_z(degrees_to_radians(angle)),
+            Self::Rotate { axis, angle } => {
+                Affine3A::from_axis_angle(Vec3::from(axis).normalize(), degrees_to_radians(angle))
+            }
+            Self::Scale(scale) => Affine3A::from_scale(scale.into()),
+            Self::Matrix(rows) => Affine3A::from_mat4(Mat4::from_cols_array_2d(&rows).transpose()),
+        }
+    }
+}
+
+fn matrix(transforms: &[TransformDescription]) -> Affine3A {
+    transforms
+        .iter()
+        .fold(Affine3A::IDENTITY, |matrix, transform| {
+            transform.matrix() * matrix
+        })
+}
+
+fn transform(mesh: SharedMesh, transforms: &[TransformDescription]) -> SharedMesh {
+    if transforms.is_empty() {
+        return mesh;
+    }
+
+    Arc::new(Transform::new(mesh, matrix(transforms)))
+}
+
+/// Pose at `time`; rotations are in degrees about the x, then y, then z axis.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDescription {
+    time: f32,
+    #[serde(default)]
+    translate: [f32; 3],
+    #[serde(default)]
+    rotate: [f32; 3],
+    #[serde(default = "default_keyframe_scale")]
+    scale: [f32; 3],
+}
+
+impl KeyframeDescription {
+    fn build(&self) -> Keyframe {
+        let [x, y, z] = self.rotate.map(degrees_to_radians);
+
+        Keyframe::new(self.time)
+            .with_translation(self.translate.into())
+            .with_rotation(Quat::from_euler(EulerRot::ZYX, z, y, x))
+            .with_scale(self.scale.into())
+    }
+}
+
+fn default_keyframe_scale() -> [f32; 3] {
+    [1.; 3]
+}
+
+fn animate(mesh: SharedMesh, keyframes: &[KeyframeDescription]) -> SharedMesh {
+    if keyframes.is_empty() {
+        return mesh;
+    }
+
+    let keyframes = keyframes.iter().map(KeyframeDescription::build).collect();
+    Arc::new(AnimatedTransform::new(mesh, keyframes))
+}
+
+/// Group of objects shared by its instances.
+#[derive(Clone)]
+struct Prototype {
+    mesh: SharedMesh,
+    /// Emissive objects of the group, sampled directly through every
+    /// instance that keeps their materials.
+    lights: Option<SharedMesh>,
+}
+
+fn find_prototype(
+    prototypes: &HashMap<String, Prototype>,
+    name: &str,
+) -> Result<Prototype, SceneError> {
+    prototypes
+        .get(name)
+        .cloned()
+        .ok_or_else(|| SceneError::UnknownPrototype(name.into()))
+}
+
+fn color([red, green, blue]: [f32; 3]) -> Color {
+    Color::linear_rgb(red, green, blue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Ray;
+
+    fn parse(source: &str) -> Result<Scene, SceneError> {
+        let description: SceneDescription = toml::from_str(source).unwrap();
+        description.build(Path::new(""))
+    }
+
+    /// Distance to the nearest hit of a ray at `time` from `origin` straight
+    /// down the z axis.
+    fn distance(world: &World, origin: [f32; 3], time: f32) -> Option<f32> {
+        let ray = Ray::new(origin.into(), Vec3::NEG_Z, time);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let hit = world.hit(&ray, &(0.001..f32::INFINITY).into(), &mut rng)?;
+        Some(hit.distance)
+    }
+
+    const SCENE: &str = r#"
+        [camera]
+        width = 32
+        height = 24
+        vfov = 30
+        lookfrom = [0, 0, 10]
+        shutter_close = 0.5
+
+        [materials.white]
+        type = "lambertian"
+        color = [0.7, 0.7, 0.7]
+
+        [materials.light]
+        type = "diffuse_light"
+        color = [4, 4, 4]
+
+        [[prototypes.lamp]]
+        type = "sphere"
+        center = [0, 0, 0]
+        radius = 1
+        material = "white"
+
+        [[prototypes.lamp]]
+        type = "quad"
+        corner = [-0.5, 2, -0.5]
+        u = [1, 0, 0]
+        v = [0, 0, 1]
+        material = "light"
+
+        # Scaled about the origin, then moved.
+        [[objects]]
+        type = "instance"
+        prototype = "lamp"
+        transform = [{ scale = [2, 2, 2] }, { translate = [10, 0, 0] }]
+
+        # The override turns the quad into a plain surface.
+        [[objects]]
+        type = "instance"
+        prototype = "lamp"
+        material = "white"
+        transform = [{ translate = [-10, 0, 0] }]
+
+        [[objects]]
+        type = "sphere"
+        center = [0, 0, 0]
+        radius = 0.5
+        material = "white"
+        keyframes = [
+            { time = 0, translate = [0, 20, 0] },
+            { time = 1, translate = [0, 30, 0] },
+        ]
+
+        [animation]
+        end = 3
+
+        [[animation.camera]]
+        frame = 2
+        lookfrom = [0, 5, 10]
+    "#;
+
+    #[test]
+    fn loads_prototypes_instances_transforms_and_keyframes() {
+        let scene = parse(SCENE).unwrap();
+
+        assert_eq!((scene.camera.width, scene.camera.height), (32, 24));
+        assert_eq!(scene.camera.vfov, 30.);
+        assert_eq!(scene.camera.shutter_close, 0.5);
+
+        assert_eq!(scene.world.len(), 3);
+        // Only the quad of the instance keeping its materials emits.
+        assert_eq!(scene.world.lights().len(), 1);
+
+        // The first instance is a sphere of radius 2 around (10, 0, 0).
+        assert!((distance(&scene.world, [10., 0., 10.], 0.).unwrap() - 8.).abs() < 1e-4);
+        assert!((distance(&scene.world, [-10., 0., 10.], 0.).unwrap() - 9.).abs() < 1e-4);
+        assert_eq!(distance(&scene.world, [20., 0., 10.], 0.), None);
+
+        // The keyframed sphere moves up by 10 over a frame.
+        assert!(distance(&scene.world, [0., 20., 10.], 0.).is_some());
+        assert!(distance(&scene.world, [0., 25., 10.], 0.5).is_some());
+        assert_eq!(distance(&scene.world, [0., 20., 10.], 0.5), None);
+
+        let animation = scene.animation.unwrap();
+        assert_eq!(animation.frames, 0..3);
+        let frame = animation.frame_config(&scene.camera, 2);
+        assert_eq!(frame.lookfrom, Vec3::new(0., 5., 10.));
+        assert_eq!((frame.shutter_open, frame.shutter_close), (2., 2.5));
+    }
+
+    #[test]
+    fn invalid_scenes_are_errors() {
+        let error = |source: &str| parse(source).err().expect("scene should not load");
+
+        assert!(matches!(
+            error("[camera]\nshutter_open = 0.75\nshutter_close = 0.25\n"),
+            SceneError::InvertedShutter(open, close) if open == 0.75 && close == 0.25
+        ));
+        assert!(matches!(
+            error("[camera]\nshutter_close = 1.5\n"),
+            SceneError::ShutterTime(time) if time == 1.5
+        ));
+
+        let object = |material: &str| {
+            format!("[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n{material}\n")
+        };
+        assert!(matches!(
+            error(&object("material = \"missing\"")),
+            SceneError::UnknownMaterial(name) if name == "missing"
+        ));
+        assert!(matches!(error(&object("")), SceneError::MissingMaterial));
+        assert!(matches!(
+            error("[[objects]]\ntype = \"instance\"\nprototype = \"missing\"\n"),
+            SceneError::UnknownPrototype(name) if name == "missing"
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
 use bevy_color::{Color, LinearRgba};
 use bevy_math::{Vec2, Vec3};
@@ -11,6 +11,12 @@ pub trait Texture {
     fn value(&self, uv: Vec2, point: Vec3) -> LinearRgba;
 }
 
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, uv: Vec2, point: Vec3) -> LinearRgba {
+        self.as_ref().value(uv, point)
+    }
+}
+
 pub struct SolidTexture {
     pub albedo: LinearRgba,
 }
@@ -146,14 +152,13 @@ struct Perlin<const N: usize> {
 
 impl<const N: usize> Perlin<N> {
     fn new(mut rng: impl Rng) -> Self {
-        let mut values = [Vec3::ZERO; N];
-        for i in 0..N {
-            values[i] = Vec3::new(
+        let values = std::array::from_fn(|_| {
+            Vec3::new(
                 rng.gen_range(-1.0..1.),
                 rng.gen_range(-1.0..1.),
                 rng.gen_range(-1.0..1.),
-            );
-        }
+            )
+        });
 
         let perm_x = Self::generate_perm(&mut rng);
         let perm_y = Self::generate_perm(&mut rng);
@@ -168,17 +173,11 @@ impl<const N: usize> Perlin<N> {
     }
 
     fn generate_perm(mut rng: impl Rng) -> [usize; N] {
-        let mut perm = [0; N];
-
-        for i in 0..N {
-            perm[i] = i;
-        }
+        let mut perm = std::array::from_fn(|i| i);
 
         for i in (1..N).rev() {
             let target = rng.gen_range(0..i);
-            let tmp = perm[i];
-            perm[i] = perm[target];
-            perm[target] = tmp;
+            perm.swap(i, target);
         }
 
         perm
@@ -194,10 +193,10 @@ impl<const N: usize> Perlin<N> {
         let k = point.z.floor() as i32;
         let mut c = [[[Vec3::ZERO; 2]; 2]; 2];
 
-        for di in 0..2 {
-            for dj in 0..2 {
-                for dk in 0..2 {
-                    c[di][dj][dk] = self.values[self.perm_x[((i + di as i32) & 255) as usize]
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, value) in row.iter_mut().enumerate() {
+                    *value = self.values[self.perm_x[((i + di as i32) & 255) as usize]
                         ^ self.perm_y[((j + dj as i32) & 255) as usize]
                         ^ self.perm_z[((k + dk as i32) & 255) as usize]];
                 }
@@ -213,14 +212,14 @@ impl<const N: usize> Perlin<N> {
         let ww = w * w * (3. - 2. * w);
         let mut accum = 0.;
 
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, value) in row.iter().enumerate() {
                     let weight_v = Vec3::new(u - i as f32, v - j as f32, w - k as f32);
                     accum += (i as f32 * uu + (1. - i as f32) * (1. - uu))
                         * (j as f32 * vv + (1. - j as f32) * (1. - vv))
                         * (k as f32 * ww + (1. - k as f32) * (1. - ww))
-                        * c[i][j][k].dot(weight_v);
+                        * value.dot(weight_v);
                 }
             }
         }
@@ -0,0 +1,180 @@
+use bevy_color::LinearRgba;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Order in which the tiles of an image are handed out for rendering.
+#[derive(Clone, Copy, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TileOrder {
+    /// Row by row from the top left.
+    #[default]
+    Scanline,
+    /// Outwards from the center, where the subject usually is.
+    Spiral,
+    /// Along a Hilbert curve, keeping consecutive tiles next to each other.
+    Hilbert,
+}
+
+/// Rectangle of pixels rendered as a unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Coordinates of the pixels in the tile, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(|y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+/// A tile that has just taken more samples.
+pub struct RenderedTile {
+    pub tile: Tile,
+    /// Mean of the samples taken so far in every pixel of the tile, row by row.
+    pub pixels: Vec<LinearRgba>,
+}
+
+/// Splits an image into tiles of at most `size` pixels square, in `order`.
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+
+    let tile = |column: u32, row: u32| {
+        let (x, y) = (column * size, row * size);
+        Tile {
+            x,
+            y,
+            width: size.min(width - x),
+            height: size.min(height - y),
+        }
+    };
+
+    let mut cells: Vec<(u32, u32)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => cells = spiral(columns, rows),
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|(column, row)| tile(column, row))
+        .collect()
+}
+
+/// Cells of a `columns` by `rows` grid visited by a square spiral walking
+/// outwards from the center.
+fn spiral(columns: u32, rows: u32) -> Vec<(u32, u32)> {
+    let count = columns as usize * rows as usize;
+    let mut cells = Vec::with_capacity(count);
+    let (mut x, mut y) = (((columns as i64) - 1) / 2, ((rows as i64) - 1) / 2);
+    let (mut dx, mut dy) = (1, 0);
+    let mut leg = 1;
+
+    while cells.len() < count {
+        // Every two turns the legs of the spiral grow by one cell.
+        for _ in 0..2 {
+            for _ in 0..leg {
+                if (0..columns as i64).contains(&x) && (0..rows as i64).contains(&y) {
+                    cells.push((x as u32, y as u32));
+                }
+                x += dx;
+                y += dy;
+            }
+            (dx, dy) = (-dy, dx);
+        }
+        leg += 1;
+    }
+
+    cells
+}
+
+/// Distance along the Hilbert curve filling a `side` by `side` grid, where
+/// `side` is a power of two.
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut scale = side / 2;
+    while scale > 0 {
+        let rx = (x & scale != 0) as u32;
+        let ry = (y & scale != 0) as u32;
+        index += scale as u64 * scale as u64 * ((3 * rx) ^ ry) as u64;
+
+        // Rotate the quadrant so that the curve inside it starts and ends
+        // next to its neighbours.
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            (x, y) = (y, x);
+        }
+        scale /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDERS: [TileOrder; 3] = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        let sizes = [(1, 1), (64, 64), (100, 37), (37, 100), (5, 300), (33, 32)];
+        for order in ORDERS {
+            for (width, height) in sizes {
+                for size in [0, 1, 7, 16, 32, 500] {
+                    let mut counts = vec![0; width as usize * height as usize];
+                    for tile in tiles(width, height, size, order) {
+                        assert!(tile.width <= size.max(1) && tile.height <= size.max(1));
+                        for (x, y) in tile.pixels() {
+                            assert!(x < width && y < height);
+                            counts[y as usize * width as usize + x as usize] += 1;
+                        }
+                    }
+                    assert!(
+                        counts.iter().all(|&count| count == 1),
+                        "{width}x{height} in tiles of {size}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scanline_goes_row_by_row() {
+        let origins: Vec<_> = tiles(5, 3, 2, TileOrder::Scanline)
+            .iter()
+            .map(|tile| (tile.x, tile.y))
+            .collect();
+        assert_eq!(origins, [(0, 0), (2, 0), (4, 0), (0, 2), (2, 2), (4, 2)]);
+    }
+
+    #[test]
+    fn spiral_starts_in_the_center() {
+        let tiles = tiles(90, 50, 10, TileOrder::Spiral);
+        assert_eq!((tiles[0].x, tiles[0].y), (40, 20));
+        assert_eq!((tiles[1].x, tiles[1].y), (50, 20));
+    }
+
+    #[test]
+    fn hilbert_tiles_follow_each_other() {
+        let tiles = tiles(128, 128, 16, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let distance = pair[0].x.abs_diff(pair[1].x) + pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(distance, 16);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use bevy_math::{Mat3, Vec3};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Color temperature, in kelvin, that white balance leaves untouched.
+pub const NEUTRAL_TEMPERATURE: f32 = 6500.;
+
+/// Operator compressing scene radiance into the displayable range.
+#[derive(Clone, Copy, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToneMapping {
+    /// Clips every channel to 1.
+    #[default]
+    Clamp,
+    /// Maps luminance `l` to `l / (1 + l)`, keeping the hue.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference and output transforms.
+    Aces,
+    /// Log encoding followed by a sigmoid, desaturating bright colors like AgX.
+    Agx,
+}
+
+impl ToneMapping {
+    /// Maps linear sRGB radiance to linear sRGB within `[0, 1]`.
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO);
+
+        let mapped = match self {
+            Self::Clamp => color,
+            Self::Reinhard => reinhard(color),
+            Self::Aces => aces(color),
+            Self::Agx => agx(color),
+        };
+
+        mapped.clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+/// Per-channel gains that make light of the given temperature appear white.
+pub fn white_balance(temperature: f32) -> Vec3 {
+    let gains = blackbody(NEUTRAL_TEMPERATURE) / blackbody(temperature);
+    gains / gains.y
+}
+
+/// Approximate linear sRGB color of a black body, after Tanner Helland's fit.
+fn blackbody(temperature: f32) -> Vec3 {
+    let t = temperature.clamp(2000., 40000.) / 100.;
+
+    let red = if t <= 66. {
+        255.
+    } else {
+        329.69873 * (t - 60.).powf(-0.13320476)
+    };
+    let green = if t <= 66. {
+        99.4708 * t.ln() - 161.11957
+    } else {
+        288.12217 * (t - 60.).powf(-0.075514846)
+    };
+    let blue = if t >= 66. {
+        255.
+    } else {
+        138.51773 * (t - 10.).ln() - 305.0448
+    };
+
+    let srgb = (Vec3::new(red, green, blue) / 255.).clamp(Vec3::splat(1e-3), Vec3::ONE);
+    // Undo the sRGB transfer function the fit was made in.
+    srgb.powf(2.2)
+}
+
+/// Relative luminance of linear sRGB.
+pub(crate) fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn reinhard(color: Vec3) -> Vec3 {
+    let l = luminance(color);
+    if l <= 0. {
+        return Vec3::ZERO;
+    }
+
+    color * (1. / (1. + l))
+}
+
+fn aces(color: Vec3) -> Vec3 {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: Mat3 = Mat3::from_cols_array(&[
+        0.59719, 0.07600, 0.02840, 0.35458, 0.90834, 0.13383, 0.04823, 0.01566, 0.83777,
+    ]);
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: Mat3 = Mat3::from_cols_array(&[
+        1.60475, -0.10208, -0.00327, -0.53108, 1.10813, -0.07276, -0.07367, -0.00605, 1.07602,
+    ]);
+
+    let v = INPUT * color;
+    let a = v * (v + 0.0245786) - 9.0537e-05;
+    let b = v * (0.983729 * v + 0.432951) + 0.238081;
+    OUTPUT * (a / b)
+}
+
+fn agx(color: Vec3) -> Vec3 {
+    const INSET: Mat3 = Mat3::from_cols_array(&[
+        0.84247906,
+        0.042328242,
+        0.042375655,
+        0.0784336,
+        0.87846864,
+        0.0784336,
+        0.079223745,
+        0.07916613,
+        0.879143,
+    ]);
+    const OUTSET: Mat3 = Mat3::from_cols_array(&[
+        1.196879,
+        -0.052896852,
+        -0.052971636,
+        -0.09802088,
+        1.1519031,
+        -0.09804345,
+        -0.09902974,
+        -0.098961177,
+        1.1510737,
+    ]);
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let encoded = (INSET * color).max(Vec3::splat(1e-10));
+    let x = ((Vec3::new(encoded.x.log2(), encoded.y.log2(), encoded.z.log2()) - MIN_EV)
+        / (MAX_EV - MIN_EV))
+        .clamp(Vec3::ZERO, Vec3::ONE);
+
+    // Polynomial approximation of the default AgX contrast curve.
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve =
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232;
+
+    (OUTSET * curve).max(Vec3::ZERO).powf(2.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapping; 3] =
+        [ToneMapping::Reinhard, ToneMapping::Aces, ToneMapping::Agx];
+
+    #[test]
+    fn operators_map_black_to_black() {
+        for operator in OPERATORS {
+            assert_eq!(operator.apply(Vec3::ZERO), Vec3::ZERO);
+        }
+    }
+
+    /// Maps `color` at exposures from 2^-12 to 2^12, in quarter stops.
+    fn exposures(operator: ToneMapping, color: Vec3) -> impl Iterator<Item = Vec3> {
+        (-48..=48).map(move |stop| operator.apply(color * 2f32.powf(stop as f32 / 4.)))
+    }
+
+    #[test]
+    fn operators_are_monotonic_and_saturate() {
+        for operator in OPERATORS {
+            let mut previous = Vec3::ZERO;
+            for mapped in exposures(operator, Vec3::ONE) {
+                assert!(mapped.cmpge(previous).all(), "{mapped} after {previous}");
+                assert!(mapped.cmple(Vec3::ONE).all(), "{mapped}");
+                previous = mapped;
+            }
+            assert!(previous.cmpge(Vec3::splat(0.95)).all(), "{previous}");
+
+            for color in [Vec3::new(1., 0.5, 0.2), Vec3::new(0.1, 0.3, 1.)] {
+                let mut previous = 0.;
+                for mapped in exposures(operator, color) {
+                    // Once a channel clips, AgX's outset matrix dims it a
+                    // little as the others catch up.
+                    assert!(luminance(mapped) >= previous - 1e-3, "{mapped}");
+                    assert!(mapped.cmple(Vec3::ONE).all(), "{mapped}");
+                    previous = luminance(mapped);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neutral_white_balance_is_the_identity() {
+        let gains = white_balance(NEUTRAL_TEMPERATURE);
+        assert!(gains.abs_diff_eq(Vec3::ONE, 1e-6), "{gains}");
+
+        // Warmer light needs less red to look white, cooler light less blue.
+        assert!(white_balance(3000.).x < 1.);
+        assert!(white_balance(10000.).z < 1.);
+    }
+}
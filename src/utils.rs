@@ -1,7 +1,7 @@
-use bevy_math::Vec3;
+use bevy_math::{Vec2, Vec3};
 use rand::Rng;
 
-pub const PI: f32 = 3.1415926535897932385;
+pub const PI: f32 = std::f32::consts::PI;
 
 pub fn random_vec(mut rng: impl Rng) -> Vec3 {
     Vec3::new(
@@ -34,10 +34,57 @@ pub fn random_vec_in_unit_disk(mut rng: impl Rng) -> Vec3 {
     }
 }
 
+/// Maps `u` in `[0, 1)²` onto the unit disk, keeping nearby points nearby,
+/// after Shirley and Chiu's concentric mapping.
+pub fn concentric_disk(u: Vec2) -> Vec2 {
+    let offset = 2. * u - Vec2::ONE;
+    if offset == Vec2::ZERO {
+        return Vec2::ZERO;
+    }
+
+    let (radius, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, PI / 4. * (offset.y / offset.x))
+    } else {
+        (offset.y, PI / 2. - PI / 4. * (offset.x / offset.y))
+    };
+
+    radius * Vec2::new(theta.cos(), theta.sin())
+}
+
 pub fn random_unit_vec(rng: impl Rng) -> Vec3 {
     random_vec_in_unit_sphere(rng).normalize()
 }
 
+/// Direction towards a sphere of `radius` whose center lies on the z axis at
+/// `distance_squared` from the origin, uniformly distributed over the cone it subtends.
+pub fn random_to_sphere(mut rng: impl Rng, radius: f32, distance_squared: f32) -> Vec3 {
+    let r1 = rng.gen::<f32>();
+    let r2 = rng.gen::<f32>();
+    let z = 1. + r2 * ((1. - radius * radius / distance_squared).max(0.).sqrt() - 1.);
+
+    let phi = 2. * PI * r1;
+    let sin_theta = (1. - z * z).sqrt();
+
+    Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+}
+
+/// Cosine-weighted direction on the hemisphere around the z axis.
+pub fn random_cosine_direction(mut rng: impl Rng) -> Vec3 {
+    let r1 = rng.gen::<f32>();
+    let r2 = rng.gen::<f32>();
+
+    let phi = 2. * PI * r1;
+    let r2_sqrt = r2.sqrt();
+
+    Vec3::new(phi.cos() * r2_sqrt, phi.sin() * r2_sqrt, (1. - r2).sqrt())
+}
+
+/// Transforms `v` from a local basis whose z axis is the unit vector `w` to world space.
+pub fn from_local(w: Vec3, v: Vec3) -> Vec3 {
+    let (a, b) = w.any_orthonormal_pair();
+    v.x * a + v.y * b + v.z * w
+}
+
 pub fn near_zero(v: Vec3) -> bool {
     let s = 1e-8;
     v.x.abs() < s && v.y.abs() < s && v.z.abs() < s